@@ -0,0 +1,515 @@
+//! Derive macros for the `TlvEncode` and `TlvDecode` traits of `dusk-tlv`.
+//!
+//! Structs and tuple structs are encoded as a container of their fields, newtype structs are
+//! encoded as their inner field, and unit structs as an empty record. Enums are encoded as a
+//! container whose first record is the variant index, followed by the variant fields.
+//!
+//! The behavior can be customized with `#[tlv(...)]` attributes:
+//!
+//! - `tag = N` on a field defines its position in the container, so fields can be reordered in
+//!   the source without changing the encoding. Fields without a tag use their declaration index.
+//! - `tag = N` on a variant defines its index.
+//! - `skip` on a field omits it from the encoding. It is decoded as [`Default::default`].
+//! - `default` on a field decodes it as [`Default::default`] if the container was exhausted
+//!   before it, so fields can be appended to a struct without breaking older payloads.
+//! - `bytes` on a field encodes it as a single record of its bytes, via `AsRef<[u8]>`, and
+//!   decodes it via `From<Vec<u8>>`. `bytes = N` additionally enforces the length of the array.
+//! - `with = "module"` on a field encodes it via `module::encode(&value, writer)` and decodes it
+//!   via `module::decode(reader)`, with the same signatures of the traits.
+
+#![deny(missing_docs)]
+
+extern crate proc_macro;
+
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Fields, Generics, Ident, Lit,
+    Meta, NestedMeta, Path, Type,
+};
+
+/// Derive `TlvEncode` for a struct or enum
+#[proc_macro_derive(TlvEncode, attributes(tlv))]
+pub fn derive_tlv_encode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_encode(input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// Derive `TlvDecode` for a struct or enum
+#[proc_macro_derive(TlvDecode, attributes(tlv))]
+pub fn derive_tlv_decode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_decode(input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+#[derive(Default)]
+struct Attrs {
+    tag: Option<u32>,
+    skip: bool,
+    default: bool,
+    bytes: Option<Option<usize>>,
+    with: Option<Path>,
+}
+
+impl Attrs {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut out = Attrs::default();
+
+        for attr in attrs.iter().filter(|a| a.path.is_ident("tlv")) {
+            let list = match attr.parse_meta()? {
+                Meta::List(l) => l,
+                m => return Err(syn::Error::new_spanned(m, "expected `#[tlv(...)]`")),
+            };
+
+            for nested in list.nested {
+                match nested {
+                    NestedMeta::Meta(Meta::Path(p)) if p.is_ident("skip") => out.skip = true,
+                    NestedMeta::Meta(Meta::Path(p)) if p.is_ident("default") => out.default = true,
+                    NestedMeta::Meta(Meta::Path(p)) if p.is_ident("bytes") => {
+                        out.bytes = Some(None)
+                    }
+                    NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("tag") => {
+                        out.tag = Some(parse_int(&nv.lit)?)
+                    }
+                    NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("bytes") => {
+                        out.bytes = Some(Some(parse_int(&nv.lit)?))
+                    }
+                    NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("with") => {
+                        out.with = match &nv.lit {
+                            Lit::Str(s) => Some(s.parse()?),
+                            l => return Err(syn::Error::new_spanned(l, "expected a path string")),
+                        }
+                    }
+                    n => return Err(syn::Error::new_spanned(n, "unknown tlv attribute")),
+                }
+            }
+
+            if out.bytes.is_some() && out.with.is_some() {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "`bytes` and `with` cannot be combined",
+                ));
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+fn parse_int<N>(lit: &Lit) -> syn::Result<N>
+where
+    N: FromStr,
+    N::Err: Display,
+{
+    match lit {
+        Lit::Int(i) => i.base10_parse(),
+        l => Err(syn::Error::new_spanned(l, "expected an integer")),
+    }
+}
+
+struct Field {
+    ident: Option<Ident>,
+    binding: Ident,
+    ty: Type,
+    attrs: Attrs,
+}
+
+/// Fields of a struct or variant, with the indexes of the encoded fields in wire order
+struct Shape {
+    fields: Vec<Field>,
+    named: bool,
+    unit: bool,
+    order: Vec<usize>,
+}
+
+impl Shape {
+    fn parse(fields: &Fields) -> syn::Result<Self> {
+        let mut parsed = vec![];
+        for (i, f) in fields.iter().enumerate() {
+            parsed.push(Field {
+                ident: f.ident.clone(),
+                binding: format_ident!("__field{}", i),
+                ty: f.ty.clone(),
+                attrs: Attrs::parse(&f.attrs)?,
+            });
+        }
+
+        let mut tags = BTreeSet::new();
+        let mut order = vec![];
+        for (i, (field, f)) in fields.iter().zip(parsed.iter()).enumerate() {
+            if f.attrs.skip {
+                continue;
+            }
+
+            let tag = f.attrs.tag.unwrap_or(i as u32);
+            if !tags.insert(tag) {
+                return Err(syn::Error::new_spanned(field, "duplicated tlv tag"));
+            }
+
+            order.push((tag, i));
+        }
+        order.sort();
+
+        Ok(Shape {
+            fields: parsed,
+            named: matches!(fields, Fields::Named(_)),
+            unit: matches!(fields, Fields::Unit),
+            order: order.into_iter().map(|(_, i)| i).collect(),
+        })
+    }
+
+    /// Newtypes are encoded as their inner field
+    fn is_newtype(&self) -> bool {
+        !self.named && self.fields.len() == 1 && self.order.len() == 1
+    }
+
+    /// Pattern that binds the encoded fields of `path` by reference
+    fn pattern(&self, path: &TokenStream2) -> TokenStream2 {
+        let bindings = self.fields.iter().map(|f| {
+            let binding = if f.attrs.skip {
+                quote!(_)
+            } else {
+                let b = &f.binding;
+                quote!(#b)
+            };
+
+            match &f.ident {
+                Some(ident) => quote!(#ident: #binding),
+                None => binding,
+            }
+        });
+
+        self.wrap(path, bindings)
+    }
+
+    /// Expression that constructs `path` from the bindings
+    fn construct(&self, path: &TokenStream2) -> TokenStream2 {
+        let bindings = self.fields.iter().map(|f| {
+            let binding = &f.binding;
+            match &f.ident {
+                Some(ident) => quote!(#ident: #binding),
+                None => quote!(#binding),
+            }
+        });
+
+        self.wrap(path, bindings)
+    }
+
+    fn wrap<I>(&self, path: &TokenStream2, items: I) -> TokenStream2
+    where
+        I: Iterator<Item = TokenStream2>,
+    {
+        if self.unit {
+            quote!(#path)
+        } else if self.named {
+            quote!(#path { #(#items),* })
+        } else {
+            quote!(#path( #(#items),* ))
+        }
+    }
+
+    /// Statements that write the encoded fields, in wire order
+    fn encode_fields(&self) -> TokenStream2 {
+        let fields = self.order.iter().map(|i| encode_field(&self.fields[*i]));
+        quote!(#(#fields)*)
+    }
+
+    /// Statements that read the encoded fields in wire order, and default the skipped ones
+    fn decode_fields(&self) -> TokenStream2 {
+        let mut stmts = vec![];
+        for i in &self.order {
+            let f = &self.fields[*i];
+            let binding = &f.binding;
+            let value = decode_field(f);
+
+            if f.attrs.default {
+                stmts.push(quote! {
+                    let #binding = if reader.is_exhausted() {
+                        ::core::default::Default::default()
+                    } else {
+                        #value
+                    };
+                });
+            } else {
+                stmts.push(quote!(let #binding = #value;));
+            }
+        }
+
+        for f in self.fields.iter().filter(|f| f.attrs.skip) {
+            let binding = &f.binding;
+            stmts.push(quote!(let #binding = ::core::default::Default::default();));
+        }
+
+        quote!(#(#stmts)*)
+    }
+}
+
+fn length_error() -> TokenStream2 {
+    quote! {
+        ::dusk_tlv::Error::Io(::std::io::Error::new(
+            ::std::io::ErrorKind::InvalidData,
+            "The length of the byte array doesnt match the field",
+        ))
+    }
+}
+
+fn encode_field(f: &Field) -> TokenStream2 {
+    let binding = &f.binding;
+
+    if let Some(with) = &f.attrs.with {
+        return quote!(#with::encode(#binding, writer)?;);
+    }
+
+    match f.attrs.bytes {
+        Some(len) => {
+            let check = len.map(|n| {
+                let err = length_error();
+                quote! {
+                    if bytes.len() != #n {
+                        return Err(#err);
+                    }
+                }
+            });
+
+            quote! {{
+                let bytes: &[u8] = ::core::convert::AsRef::<[u8]>::as_ref(#binding);
+                #check
+                writer.write_record(bytes)?;
+            }}
+        }
+
+        None => quote!(::dusk_tlv::TlvEncode::encode(#binding, writer)?;),
+    }
+}
+
+fn decode_field(f: &Field) -> TokenStream2 {
+    let ty = &f.ty;
+
+    if let Some(with) = &f.attrs.with {
+        return quote!(#with::decode(reader)?);
+    }
+
+    match f.attrs.bytes {
+        Some(len) => {
+            let check = len.map(|n| {
+                let err = length_error();
+                quote! {
+                    if bytes.len() != #n {
+                        return Err(#err);
+                    }
+                }
+            });
+
+            quote! {{
+                let bytes = reader.next_payload()?;
+                #check
+                <#ty as ::core::convert::From<::std::vec::Vec<u8>>>::from(bytes)
+            }}
+        }
+
+        None => quote!(<#ty as ::dusk_tlv::TlvDecode>::decode(reader)?),
+    }
+}
+
+fn add_bounds(generics: &Generics, bound: Path) -> Generics {
+    let mut generics = generics.clone();
+    let params: Vec<Ident> = generics.type_params().map(|p| p.ident.clone()).collect();
+
+    let where_clause = generics.make_where_clause();
+    for p in params {
+        where_clause.predicates.push(parse_quote!(#p: #bound));
+    }
+
+    generics
+}
+
+/// Variants of an enum with their indexes
+fn variants(data: &syn::DataEnum) -> syn::Result<Vec<(u32, &Ident, Shape)>> {
+    let mut indexes = BTreeSet::new();
+    let mut variants = vec![];
+
+    for (i, v) in data.variants.iter().enumerate() {
+        let index = Attrs::parse(&v.attrs)?.tag.unwrap_or(i as u32);
+        if !indexes.insert(index) {
+            return Err(syn::Error::new_spanned(v, "duplicated tlv tag"));
+        }
+
+        variants.push((index, &v.ident, Shape::parse(&v.fields)?));
+    }
+
+    Ok(variants)
+}
+
+fn expand_encode(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let path = quote!(#name);
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let shape = Shape::parse(&data.fields)?;
+            let pattern = shape.pattern(&path);
+            let fields = shape.encode_fields();
+
+            if shape.unit {
+                quote!(writer.write_record(&[]).map(|_| ()))
+            } else if shape.is_newtype() {
+                quote! {
+                    let #pattern = self;
+                    #fields
+                    Ok(())
+                }
+            } else {
+                quote! {
+                    let #pattern = self;
+                    writer.write_container(|writer| {
+                        #fields
+                        Ok(())
+                    })
+                }
+            }
+        }
+
+        Data::Enum(data) if data.variants.is_empty() => quote!(match *self {}),
+
+        Data::Enum(data) => {
+            let arms = variants(data)?.into_iter().map(|(index, ident, shape)| {
+                let pattern = shape.pattern(&quote!(#name::#ident));
+                let fields = shape.encode_fields();
+
+                quote! {
+                    #pattern => writer.write_variant(#index, |writer| {
+                        #fields
+                        Ok(())
+                    }),
+                }
+            });
+
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+
+        Data::Union(_) => {
+            return Err(syn::Error::new_spanned(
+                &input,
+                "unions are not supported by TlvEncode",
+            ))
+        }
+    };
+
+    let generics = add_bounds(&input.generics, parse_quote!(::dusk_tlv::TlvEncode));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::dusk_tlv::TlvEncode for #name #ty_generics #where_clause {
+            fn encode<__W: ::std::io::Write>(
+                &self,
+                writer: &mut ::dusk_tlv::TlvWriter<__W>,
+            ) -> ::core::result::Result<(), ::dusk_tlv::Error> {
+                #body
+            }
+        }
+    })
+}
+
+fn expand_decode(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let path = quote!(#name);
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let shape = Shape::parse(&data.fields)?;
+            let construct = shape.construct(&path);
+            let fields = shape.decode_fields();
+
+            if shape.unit {
+                quote! {
+                    let payload = reader.next_payload()?;
+                    if !payload.is_empty() {
+                        return Err(::dusk_tlv::Error::TrailingBytes(payload.len()));
+                    }
+
+                    Ok(#construct)
+                }
+            } else if shape.is_newtype() {
+                if shape.fields[0].attrs.default {
+                    return Err(syn::Error::new(
+                        shape.fields[0].ty.span(),
+                        "`default` is not supported on newtypes",
+                    ));
+                }
+
+                quote! {
+                    #fields
+                    Ok(#construct)
+                }
+            } else {
+                quote! {
+                    reader.read_container(|reader| {
+                        #fields
+                        Ok(#construct)
+                    })
+                }
+            }
+        }
+
+        Data::Enum(data) => {
+            let mut arms = vec![];
+            for (index, ident, shape) in variants(data)? {
+                let construct = shape.construct(&quote!(#name::#ident));
+                let fields = shape.decode_fields();
+
+                arms.push(quote! {
+                    #index => {
+                        #fields
+                        Ok(#construct)
+                    }
+                });
+            }
+
+            quote! {
+                reader.read_container(|reader| {
+                    match <u32 as ::dusk_tlv::TlvDecode>::decode(reader)? {
+                        #(#arms)*
+                        _ => Err(::dusk_tlv::Error::Io(::std::io::Error::new(
+                            ::std::io::ErrorKind::InvalidData,
+                            "Unknown enum variant index",
+                        ))),
+                    }
+                })
+            }
+        }
+
+        Data::Union(_) => {
+            return Err(syn::Error::new_spanned(
+                &input,
+                "unions are not supported by TlvDecode",
+            ))
+        }
+    };
+
+    let generics = add_bounds(&input.generics, parse_quote!(::dusk_tlv::TlvDecode));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::dusk_tlv::TlvDecode for #name #ty_generics #where_clause {
+            fn decode<__R: ::std::io::Read>(
+                reader: &mut ::dusk_tlv::TlvReader<__R>,
+            ) -> ::core::result::Result<Self, ::dusk_tlv::Error> {
+                #body
+            }
+        }
+    })
+}
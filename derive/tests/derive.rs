@@ -0,0 +1,196 @@
+use dusk_tlv::{from_slice, to_vec, Error, TlvDecode, TlvEncode, TlvReader, TlvWriter};
+
+use std::io;
+
+#[derive(Debug, Clone, PartialEq, TlvEncode, TlvDecode)]
+struct Header {
+    id: u64,
+    name: String,
+    flags: Option<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, TlvEncode, TlvDecode)]
+struct Id(u32);
+
+#[derive(Debug, Clone, PartialEq, TlvEncode, TlvDecode)]
+struct Pair(u8, i16);
+
+#[derive(Debug, Clone, PartialEq, TlvEncode, TlvDecode)]
+struct Unit;
+
+#[derive(Debug, Clone, PartialEq, TlvEncode, TlvDecode)]
+enum Kind {
+    Empty,
+    Amount(u64),
+    Pair(u8, i16),
+    Named {
+        id: u32,
+        tag: Option<String>,
+    },
+    #[tlv(tag = 10)]
+    Late,
+}
+
+#[derive(Debug, Clone, PartialEq, TlvEncode, TlvDecode)]
+struct Wrapper<T> {
+    items: Vec<T>,
+}
+
+mod doubled {
+    use dusk_tlv::{Error, TlvDecode, TlvEncode, TlvReader, TlvWriter};
+    use std::io;
+
+    pub fn encode<W: io::Write>(v: &u32, writer: &mut TlvWriter<W>) -> Result<(), Error> {
+        (v * 2).encode(writer)
+    }
+
+    pub fn decode<R: io::Read>(reader: &mut TlvReader<R>) -> Result<u32, Error> {
+        u32::decode(reader).map(|v| v / 2)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, TlvEncode, TlvDecode)]
+struct Attributes {
+    #[tlv(tag = 2)]
+    last: u8,
+    #[tlv(tag = 0)]
+    first: u8,
+    #[tlv(skip)]
+    cache: Vec<u8>,
+    #[tlv(bytes = 4)]
+    key: Vec<u8>,
+    #[tlv(with = "doubled")]
+    half: u32,
+    #[tlv(default, tag = 10)]
+    added: Option<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, TlvEncode, TlvDecode)]
+struct Older {
+    first: u8,
+    last: u8,
+    key: [u8; 4],
+    half: u32,
+}
+
+fn roundtrip<T: TlvEncode + TlvDecode>(value: &T) -> T {
+    let mut writer = TlvWriter::new(vec![]);
+    writer.encode(value).unwrap();
+    let v = writer.into_inner();
+
+    let mut reader = TlvReader::new(v.as_slice());
+    let output = reader.decode().unwrap();
+    assert!(reader.next().is_none());
+
+    output
+}
+
+#[test]
+fn derive_roundtrip() {
+    let header = Header {
+        id: 25,
+        name: String::from("Foo"),
+        flags: None,
+    };
+    assert_eq!(header, roundtrip(&header));
+    assert_eq!(Id(7), roundtrip(&Id(7)));
+    assert_eq!(Pair(1, -2), roundtrip(&Pair(1, -2)));
+    assert_eq!(Unit, roundtrip(&Unit));
+
+    let kinds = Wrapper {
+        items: vec![
+            Kind::Empty,
+            Kind::Amount(3),
+            Kind::Pair(4, -5),
+            Kind::Named {
+                id: 6,
+                tag: Some(String::from("Bar")),
+            },
+            Kind::Late,
+        ],
+    };
+    assert_eq!(kinds, roundtrip(&kinds));
+}
+
+#[test]
+fn derive_matches_serde_layout() {
+    let mut writer = TlvWriter::new(vec![]);
+    writer.encode(&Kind::Pair(4, -5)).unwrap();
+    writer.encode(&Id(7)).unwrap();
+    let v = writer.into_inner();
+
+    let mut expected = to_vec(&(2u32, 4u8, -5i16)).unwrap();
+    expected.extend(to_vec(&7u32).unwrap());
+    assert_eq!(expected, v);
+
+    let (index, a, b): (u32, u8, i16) = from_slice(&v[..v.len() - 6]).unwrap();
+    assert_eq!((2, 4, -5), (index, a, b));
+}
+
+#[test]
+fn derive_attributes() {
+    let value = Attributes {
+        last: 3,
+        first: 1,
+        cache: vec![1, 2, 3],
+        key: vec![0xaa; 4],
+        half: 21,
+        added: Some(5),
+    };
+
+    let mut writer = TlvWriter::new(vec![]);
+    writer.encode(&value).unwrap();
+    let v = writer.into_inner();
+
+    let mut reader = TlvReader::new(v.as_slice());
+    let output: Attributes = reader.decode().unwrap();
+    assert!(output.cache.is_empty());
+    assert_eq!(
+        Attributes {
+            cache: vec![],
+            ..value.clone()
+        },
+        output
+    );
+
+    // The field tags define the order, and the default field can be omitted
+    let older = Older {
+        first: 1,
+        last: 3,
+        key: [0xaa; 4],
+        half: 42,
+    };
+
+    let mut writer = TlvWriter::new(vec![]);
+    writer.encode(&older).unwrap();
+    let v = writer.into_inner();
+
+    let mut reader = TlvReader::new(v.as_slice());
+    let output: Attributes = reader.decode().unwrap();
+    assert_eq!(None, output.added);
+    assert_eq!((1, 3, 21), (output.first, output.last, output.half));
+
+    // The byte array length is enforced
+    let invalid = Attributes {
+        key: vec![0xaa; 3],
+        ..value
+    };
+
+    let mut writer = TlvWriter::new(vec![]);
+    match writer.encode(&invalid) {
+        Err(Error::Io(e)) => assert_eq!(io::ErrorKind::InvalidData, e.kind()),
+        r => panic!("Unexpected result {:?}", r),
+    }
+
+    // The failed value leaves nothing behind, and the writer is still usable
+    writer.encode(&Id(7)).unwrap();
+    assert_eq!(to_vec(&7u32).unwrap(), writer.into_inner());
+}
+
+#[test]
+fn derive_unknown_variant() {
+    let v = to_vec(&(3u32,)).unwrap();
+
+    let mut reader = TlvReader::new(v.as_slice());
+    assert!(reader.decode::<Kind>().is_err());
+}
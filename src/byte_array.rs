@@ -0,0 +1,162 @@
+//! Serde helpers to encode fixed-size byte arrays as a single record, instead of a tuple of
+//! records.
+//!
+//! The length of the payload is checked when the array is deserialized.
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Signed {
+//!     #[serde(with = "dusk_tlv::byte_array")]
+//!     hash: [u8; 32],
+//!     #[serde(with = "dusk_tlv::byte_array::boxed")]
+//!     signature: Box<[u8; 64]>,
+//! }
+//! ```
+
+use std::fmt;
+
+use serde::de::{Error as SerdeDeError, SeqAccess, Visitor};
+use serde::{Deserializer, Serializer};
+
+/// Serialize the array as a single record
+pub fn serialize<S, const N: usize>(bytes: &[u8; N], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_bytes(&bytes[..])
+}
+
+/// Deserialize the array from a single record, granting its payload has exactly `N` bytes
+pub fn deserialize<'de, D, const N: usize>(deserializer: D) -> Result<[u8; N], D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_bytes(ByteArrayVisitor::<N>)
+}
+
+/// Helpers for boxed byte arrays, to avoid large values on the stack
+pub mod boxed {
+    use super::ByteArrayVisitor;
+
+    use serde::{Deserializer, Serializer};
+
+    /// Serialize the boxed array as a single record
+    #[allow(clippy::borrowed_box)]
+    pub fn serialize<S, const N: usize>(
+        bytes: &Box<[u8; N]>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        super::serialize(bytes, serializer)
+    }
+
+    /// Deserialize the boxed array from a single record, granting its payload has exactly `N`
+    /// bytes
+    pub fn deserialize<'de, D, const N: usize>(deserializer: D) -> Result<Box<[u8; N]>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer
+            .deserialize_bytes(ByteArrayVisitor::<N>)
+            .map(Box::new)
+    }
+}
+
+/// Accept payloads of exactly `N` bytes, or sequences of `N` bytes for formats that don't support
+/// byte slices
+struct ByteArrayVisitor<const N: usize>;
+
+impl<'de, const N: usize> Visitor<'de> for ByteArrayVisitor<N> {
+    type Value = [u8; N];
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "an array of {} bytes", N)
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+        E: SerdeDeError,
+    {
+        if v.len() != N {
+            return Err(E::invalid_length(v.len(), &self));
+        }
+
+        let mut bytes = [0x00u8; N];
+        bytes.copy_from_slice(v);
+        Ok(bytes)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut bytes = [0x00u8; N];
+        for (i, b) in bytes.iter_mut().enumerate() {
+            *b = seq
+                .next_element()?
+                .ok_or_else(|| A::Error::invalid_length(i, &self))?;
+        }
+
+        if seq.next_element::<u8>()?.is_some() {
+            return Err(A::Error::invalid_length(N + 1, &self));
+        }
+
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Signed {
+        #[serde(with = "crate::byte_array")]
+        hash: [u8; 32],
+        #[serde(with = "crate::byte_array::boxed")]
+        signature: Box<[u8; 64]>,
+    }
+
+    #[test]
+    fn byte_array_single_record() {
+        let signed = Signed {
+            hash: [0xab; 32],
+            signature: Box::new([0xcd; 64]),
+        };
+
+        let v = to_vec(&signed).unwrap();
+        assert_eq!(&[0xf1u8, 0x64, 0xf1, 0x20, 0xab], &v[..5]);
+        assert_eq!(2 + 2 + 32 + 2 + 64, v.len());
+        assert_eq!(v.len(), serialized_size(&signed).unwrap());
+        assert_eq!(v, to_value(&signed).unwrap().to_vec().unwrap());
+
+        assert_eq!(signed, from_slice(v.as_slice()).unwrap());
+        assert_eq!(signed, from_value(to_value(&signed).unwrap()).unwrap());
+
+        let mut writer = TlvWriter::new(vec![]);
+        writer.encode(&[0xabu8; 32]).unwrap();
+        assert_eq!(&v[2..36], writer.into_inner().as_slice());
+    }
+
+    #[test]
+    fn byte_array_invalid_length() {
+        for len in [31usize, 33].iter() {
+            let payload = vec![0xab; *len];
+
+            let mut writer = TlvWriter::new(vec![]);
+            let mut list = writer.begin_list();
+            list.write_item(payload.as_slice()).unwrap();
+            list.write_item(&[0xcd; 64][..]).unwrap();
+            list.finish().unwrap();
+            let v = writer.into_inner();
+
+            assert!(from_slice::<Signed>(v.as_slice()).is_err());
+        }
+    }
+}
@@ -0,0 +1,875 @@
+use crate::Error;
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io;
+use std::ops::Range;
+
+/// Reflected polynomial of CRC32C (Castagnoli)
+const CRC32C_POLY: u32 = 0x82f6_3b78;
+
+/// Amount of bytes of the checksum that follows every framed record
+pub const CHECKSUM_LEN: usize = 4;
+
+/// Marker written before every record by a [`ChecksumWriter`] with sync markers, so a reader can
+/// find the next record boundary after a corrupted one. The upper nibble of its first byte is not
+/// a TLV tag.
+pub const SYNC_MARKER: [u8; 8] = [0x5d, 0xc3, 0x7a, 0x1e, 0xe1, 0x85, 0x3c, 0xa5];
+
+/// Default maximum length of a record accepted by a [`ChecksumReader`]
+pub const DEFAULT_MAX_RECORD_LEN: usize = 16 * 1024 * 1024;
+
+const CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+const fn crc32c_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ CRC32C_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+}
+
+/// Running CRC32C of the bytes of a record
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Crc32c(u32);
+
+impl Crc32c {
+    pub(crate) fn new() -> Self {
+        Crc32c(!0)
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        for b in data {
+            self.0 = CRC32C_TABLE[((self.0 ^ *b as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub(crate) fn finalize(self) -> u32 {
+        !self.0
+    }
+}
+
+/// Header of the record being framed, collected byte by byte
+#[derive(Debug, Clone, Copy)]
+struct Header {
+    bytes: [u8; 9],
+    filled: usize,
+}
+
+impl Header {
+    fn new() -> Self {
+        Header {
+            bytes: [0x00; 9],
+            filled: 0,
+        }
+    }
+
+    /// Total length of the header, known once the type is collected
+    fn len(&self) -> Result<usize, io::Error> {
+        if self.filled == 0 {
+            return Ok(1);
+        }
+
+        let len = (self.bytes[0] & 0x0f) as usize;
+        if len > 8 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The TLV type defines more than 8 bytes for the length",
+            ));
+        }
+
+        Ok(1 + len)
+    }
+
+    fn is_complete(&self) -> Result<bool, io::Error> {
+        Ok(self.filled > 0 && self.filled == self.len()?)
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.filled]
+    }
+
+    fn payload_len(&self) -> u64 {
+        let mut len = [0x00u8; 8];
+        len[..self.filled - 1].copy_from_slice(&self.bytes[1..self.filled]);
+        u64::from_le_bytes(len)
+    }
+}
+
+/// Consumes an implementation of [`io::Write`], and append a CRC32C to every top-level TLV record
+/// written to it.
+///
+/// The checksum is 4 little-endian bytes over the header and payload of the record. Nested records
+/// are covered by the checksum of the outermost one. Use it as the writer of a
+/// [`crate::TlvWriter`], and read the output with a [`ChecksumReader`].
+#[derive(Debug)]
+pub struct ChecksumWriter<W>
+where
+    W: io::Write,
+{
+    writer: W,
+    header: Header,
+    remaining: u64,
+    crc: Crc32c,
+    sync: bool,
+}
+
+impl<W> ChecksumWriter<W>
+where
+    W: io::Write,
+{
+    /// [`ChecksumWriter`] constructor
+    pub fn new(writer: W) -> Self {
+        ChecksumWriter {
+            writer,
+            header: Header::new(),
+            remaining: 0,
+            crc: Crc32c::new(),
+            sync: false,
+        }
+    }
+
+    /// Write a [`SYNC_MARKER`] before every record
+    pub fn set_sync_markers(&mut self, sync: bool) {
+        self.sync = sync;
+    }
+
+    /// Consumes self, and return the inner writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn seal(&mut self) -> Result<(), io::Error> {
+        let crc = self.crc.finalize();
+        self.writer.write_all(&crc.to_le_bytes())?;
+
+        self.header = Header::new();
+        self.crc = Crc32c::new();
+
+        Ok(())
+    }
+}
+
+impl<W> io::Write for ChecksumWriter<W>
+where
+    W: io::Write,
+{
+    /// The header of a record is buffered until complete, and the payload is forwarded to the
+    /// inner writer. The checksum is written once the payload is complete.
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if !self.header.is_complete()? {
+            let n = cmp::min(self.header.len()? - self.header.filled, buf.len());
+            let filled = self.header.filled;
+            self.header.bytes[filled..filled + n].copy_from_slice(&buf[..n]);
+            self.header.filled += n;
+
+            if self.header.is_complete()? {
+                let header = self.header;
+                if self.sync {
+                    self.writer.write_all(&SYNC_MARKER)?;
+                }
+                self.writer.write_all(header.as_slice())?;
+                self.crc.update(header.as_slice());
+
+                self.remaining = header.payload_len();
+                if self.remaining == 0 {
+                    self.seal()?;
+                }
+            }
+
+            return Ok(n);
+        }
+
+        let max = cmp::min(buf.len() as u64, self.remaining) as usize;
+        let n = self.writer.write(&buf[..max])?;
+        self.crc.update(&buf[..n]);
+
+        self.remaining -= n as u64;
+        if self.remaining == 0 {
+            self.seal()?;
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.writer.flush()
+    }
+}
+
+/// Consumes an implementation of [`io::Read`], and provide the TLV records written by a
+/// [`ChecksumWriter`] without their checksums and sync markers.
+///
+/// The checksum of a record is verified as soon as its last byte is read. If it doesn't match,
+/// the read fails with [`Error::ChecksumMismatch`], which is recovered as such by the
+/// [`crate::TlvReader`] that reads from this one.
+///
+/// If the records are preceded by sync markers, the reader can be set to recover from corrupted
+/// records via [`ChecksumReader::set_recovery`].
+#[derive(Debug)]
+pub struct ChecksumReader<R>
+where
+    R: io::Read,
+{
+    reader: R,
+    header: Header,
+    delivered: usize,
+    remaining: u64,
+    crc: Crc32c,
+    index: usize,
+    offset: u64,
+    start: u64,
+    sync: bool,
+    recovery: bool,
+    max_record_len: usize,
+    backlog: VecDeque<u8>,
+    frame: Vec<u8>,
+    skipped: Vec<Range<u64>>,
+}
+
+/// Outcome of reading a whole framed record in recovery mode
+enum Frame {
+    Valid,
+    Exhausted,
+    /// The sync marker, header, length or checksum are invalid, or the stream is truncated
+    Corrupted,
+}
+
+impl<R> ChecksumReader<R>
+where
+    R: io::Read,
+{
+    /// [`ChecksumReader`] constructor
+    pub fn new(reader: R) -> Self {
+        ChecksumReader {
+            reader,
+            header: Header::new(),
+            delivered: 0,
+            remaining: 0,
+            crc: Crc32c::new(),
+            index: 0,
+            offset: 0,
+            start: 0,
+            sync: false,
+            recovery: false,
+            max_record_len: DEFAULT_MAX_RECORD_LEN,
+            backlog: VecDeque::new(),
+            frame: vec![],
+            skipped: vec![],
+        }
+    }
+
+    /// Expect every record to be preceded by a [`SYNC_MARKER`], as written by a
+    /// [`ChecksumWriter`] with sync markers.
+    pub fn set_sync_markers(&mut self, sync: bool) {
+        self.sync = sync;
+    }
+
+    /// Skip corrupted records instead of failing, resuming at the next sync marker. The skipped
+    /// ranges of bytes are reported by [`ChecksumReader::skipped`].
+    ///
+    /// Every record is buffered and verified before any of its bytes are provided, so the reader
+    /// never provides corrupted data. Requires sync markers.
+    pub fn set_recovery(&mut self, recovery: bool) {
+        self.recovery = recovery;
+    }
+
+    /// Maximum length of a record. Longer records are considered corrupted, so they are skipped
+    /// in recovery mode, and fail with [`Error::ChecksumMismatch`] otherwise. Defaults to
+    /// [`DEFAULT_MAX_RECORD_LEN`].
+    pub fn set_max_record_len(&mut self, max_record_len: usize) {
+        self.max_record_len = max_record_len;
+    }
+
+    /// Consumes self, and return the inner reader
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Amount of records verified so far
+    pub fn records(&self) -> usize {
+        self.index
+    }
+
+    /// Amount of bytes consumed from the inner reader
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Ranges of offsets of the bytes skipped while recovering from corrupted records
+    pub fn skipped(&self) -> &[Range<u64>] {
+        self.skipped.as_slice()
+    }
+
+    /// Read bytes that were pushed back by [`ChecksumReader::unread`] before reading from the
+    /// inner reader
+    fn read_raw(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let n = if self.backlog.is_empty() {
+            self.reader.read(buf)?
+        } else {
+            let n = cmp::min(buf.len(), self.backlog.len());
+            for (b, d) in buf.iter_mut().zip(self.backlog.drain(..n)) {
+                *b = d;
+            }
+            n
+        };
+
+        self.offset += n as u64;
+        Ok(n)
+    }
+
+    /// Push back bytes to be read again, so they can be scanned for a sync marker
+    fn unread(&mut self, bytes: &[u8]) {
+        for b in bytes.iter().rev() {
+            self.backlog.push_front(*b);
+        }
+        self.offset -= bytes.len() as u64;
+    }
+
+    /// Read the sync marker that precedes a record. Return `false` if the reader is exhausted
+    /// before it.
+    fn read_marker(&mut self) -> Result<bool, io::Error> {
+        let mut marker = [0x00u8; SYNC_MARKER.len()];
+        if self.read_raw(&mut marker[..1])? == 0 {
+            return Ok(false);
+        }
+
+        read_raw_exact(self, &mut marker[1..])?;
+        if marker != SYNC_MARKER {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The record is not preceded by a sync marker",
+            ));
+        }
+
+        Ok(true)
+    }
+
+    /// Read the header of the next record. Return `false` if the reader is exhausted before it.
+    fn read_header(&mut self) -> Result<bool, io::Error> {
+        self.start = self.offset;
+        if self.sync && !self.read_marker()? {
+            return Ok(false);
+        }
+
+        while !self.header.is_complete()? {
+            let filled = self.header.filled;
+            let len = self.header.len()?;
+
+            let mut bytes = self.header.bytes;
+            let n = self.read_raw(&mut bytes[filled..len])?;
+            self.header.bytes = bytes;
+
+            if n == 0 && filled == 0 && !self.sync {
+                return Ok(false);
+            }
+
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "The reader didnt provide enough bytes for the TLV header",
+                ));
+            }
+
+            self.header.filled += n;
+        }
+
+        let header = self.header;
+        if header.payload_len() > self.max_record_len as u64 {
+            // A damaged length cannot be trusted until the checksum is verified, so the record
+            // is rejected before its payload is requested
+            let (index, offset) = (self.index, self.start);
+
+            self.header = Header::new();
+            self.crc = Crc32c::new();
+            self.index += 1;
+
+            return Err(Error::ChecksumMismatch { index, offset }.into());
+        }
+
+        self.crc.update(header.as_slice());
+        self.remaining = header.payload_len();
+
+        Ok(true)
+    }
+
+    /// Read the checksum of the complete record, and compare it to the computed one
+    fn verify(&mut self) -> Result<(), io::Error> {
+        let mut crc = [0x00u8; CHECKSUM_LEN];
+        read_raw_exact(self, &mut crc)?;
+
+        let expected = self.crc.finalize();
+        let (index, offset) = (self.index, self.start);
+
+        self.header = Header::new();
+        self.delivered = 0;
+        self.crc = Crc32c::new();
+        self.index += 1;
+
+        if u32::from_le_bytes(crc) != expected {
+            return Err(Error::ChecksumMismatch { index, offset }.into());
+        }
+
+        Ok(())
+    }
+
+    /// Append `n` bytes of the inner reader to the frame. Return `false` if the reader is
+    /// exhausted before them.
+    fn take(&mut self, n: usize) -> Result<bool, io::Error> {
+        let mut frame = std::mem::take(&mut self.frame);
+        let start = frame.len();
+        frame.resize(start + n, 0x00);
+
+        let mut filled = start;
+        while filled < frame.len() {
+            match self.read_raw(&mut frame[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    frame.truncate(filled);
+                    self.frame = frame;
+                    return Err(e);
+                }
+            }
+        }
+
+        frame.truncate(filled);
+        self.frame = frame;
+
+        Ok(filled == start + n)
+    }
+
+    /// Read a whole record, with its sync marker and checksum, into the frame
+    fn read_frame(&mut self) -> Result<Frame, io::Error> {
+        self.frame.clear();
+        self.start = self.offset;
+
+        let marker = SYNC_MARKER.len();
+        if !self.take(marker)? {
+            return Ok(match self.frame.is_empty() {
+                true => Frame::Exhausted,
+                false => Frame::Corrupted,
+            });
+        }
+
+        if self.frame[..marker] != SYNC_MARKER {
+            return Ok(Frame::Corrupted);
+        }
+
+        let mut header = Header::new();
+        while !header.is_complete().unwrap_or(true) {
+            let n = header.len().unwrap_or(1) - header.filled;
+            if !self.take(n)? {
+                return Ok(Frame::Corrupted);
+            }
+
+            let filled = header.filled;
+            header.bytes[filled..filled + n].copy_from_slice(&self.frame[marker + filled..]);
+            header.filled += n;
+        }
+
+        if header.len().is_err() {
+            return Ok(Frame::Corrupted);
+        }
+
+        let len = header.payload_len();
+        if len > self.max_record_len as u64 {
+            return Ok(Frame::Corrupted);
+        }
+
+        if !self.take(len as usize + CHECKSUM_LEN)? {
+            return Ok(Frame::Corrupted);
+        }
+
+        let (record, crc) = self.frame[marker..].split_at(header.filled + len as usize);
+        let mut expected = Crc32c::new();
+        expected.update(record);
+        if crc != expected.finalize().to_le_bytes() {
+            return Ok(Frame::Corrupted);
+        }
+
+        Ok(Frame::Valid)
+    }
+
+    /// Skip the bytes of the stream until the next sync marker, after the first byte of the
+    /// corrupted frame
+    fn resync(&mut self) -> Result<(), io::Error> {
+        let start = self.start;
+        let marker = SYNC_MARKER.len();
+
+        // The frame is scanned in place, and then the stream in chunks. The last bytes of every
+        // chunk are kept, since they may be the beginning of a marker.
+        let mut window = std::mem::take(&mut self.frame);
+        window.drain(..cmp::min(1, window.len()));
+        let mut chunk = [0x00u8; 4096];
+        loop {
+            if let Some(pos) = window.windows(marker).position(|w| w == SYNC_MARKER) {
+                self.unread(&window[pos..]);
+                break;
+            }
+
+            let kept = window.len().saturating_sub(marker - 1);
+            window.drain(..kept);
+
+            let n = self.read_raw(&mut chunk)?;
+            if n == 0 {
+                break;
+            }
+            window.extend_from_slice(&chunk[..n]);
+        }
+
+        match self.skipped.last_mut() {
+            Some(last) if last.end == start => last.end = self.offset,
+            _ => self.skipped.push(start..self.offset),
+        }
+
+        Ok(())
+    }
+
+    /// Provide the bytes of the next valid record, skipping the corrupted ones
+    fn read_recovering(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let marker = SYNC_MARKER.len();
+
+        if self.delivered == 0 {
+            loop {
+                match self.read_frame()? {
+                    Frame::Valid => break,
+                    Frame::Exhausted => return Ok(0),
+                    Frame::Corrupted => self.resync()?,
+                }
+            }
+
+            self.index += 1;
+            self.delivered = marker;
+        }
+
+        let record = &self.frame[self.delivered..self.frame.len() - CHECKSUM_LEN];
+        let n = cmp::min(record.len(), buf.len());
+        buf[..n].copy_from_slice(&record[..n]);
+
+        self.delivered += n;
+        if self.delivered == self.frame.len() - CHECKSUM_LEN {
+            self.delivered = 0;
+        }
+
+        Ok(n)
+    }
+}
+
+impl<R> io::Read for ChecksumReader<R>
+where
+    R: io::Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.recovery {
+            return self.read_recovering(buf);
+        }
+
+        if self.header.filled == 0 && !self.read_header()? {
+            return Ok(0);
+        }
+
+        if self.delivered < self.header.filled {
+            let header = &self.header.as_slice()[self.delivered..];
+            let n = cmp::min(header.len(), buf.len());
+            buf[..n].copy_from_slice(&header[..n]);
+            self.delivered += n;
+
+            if self.delivered == self.header.filled && self.remaining == 0 {
+                self.verify()?;
+            }
+
+            return Ok(n);
+        }
+
+        let max = cmp::min(buf.len() as u64, self.remaining) as usize;
+        let n = self.read_raw(&mut buf[..max])?;
+        if n == 0 {
+            return Err(eof(
+                "The reader didnt provide enough bytes for the TLV payload",
+            ));
+        }
+
+        self.crc.update(&buf[..n]);
+        self.remaining -= n as u64;
+        if self.remaining == 0 {
+            self.verify()?;
+        }
+
+        Ok(n)
+    }
+}
+
+/// Fill the buffer with the raw bytes of the reader
+fn read_raw_exact<R: io::Read>(
+    reader: &mut ChecksumReader<R>,
+    buf: &mut [u8],
+) -> Result<(), io::Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read_raw(&mut buf[filled..]) {
+            Ok(0) => return Err(eof("The reader didnt provide enough bytes for the record")),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+fn eof(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Entry {
+        id: u64,
+        name: String,
+        tags: Vec<u16>,
+    }
+
+    fn entries() -> Vec<Entry> {
+        (0..3)
+            .map(|i| Entry {
+                id: i,
+                name: format!("Entry {}", i),
+                tags: vec![i as u16; 4],
+            })
+            .collect()
+    }
+
+    fn framed() -> Vec<u8> {
+        let mut writer = TlvWriter::new(ChecksumWriter::new(vec![]));
+        for e in entries() {
+            e.serialize(&mut writer).unwrap();
+        }
+        writer.write_record(&[]).unwrap();
+        writer.into_inner().into_inner()
+    }
+
+    #[test]
+    fn checksum_roundtrip() {
+        let v = framed();
+
+        // The checksum of "123456789" is the reference value of CRC32C
+        let mut crc = checksum::Crc32c::new();
+        crc.update(b"123456789");
+        assert_eq!(0xe306_9283, crc.finalize());
+
+        let mut plain = to_vec(&entries()[0]).unwrap();
+        let mut crc = checksum::Crc32c::new();
+        crc.update(plain.as_slice());
+        plain.extend_from_slice(&crc.finalize().to_le_bytes());
+        assert_eq!(plain.as_slice(), &v[..plain.len()]);
+
+        let mut reader = TlvReader::new(ChecksumReader::new(v.as_slice()));
+        for e in entries() {
+            assert_eq!(e, Entry::deserialize(&mut reader).unwrap());
+        }
+        assert!(reader.next().unwrap().unwrap().is_empty());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let v = framed();
+        let record = to_vec(&entries()[0]).unwrap().len() + CHECKSUM_LEN;
+
+        // Flip a bit of the payload of the second record
+        let mut corrupted = v.clone();
+        corrupted[record + 10] ^= 0x01;
+
+        let mut reader = TlvReader::new(ChecksumReader::new(corrupted.as_slice()));
+        assert_eq!(entries()[0], Entry::deserialize(&mut reader).unwrap());
+        match Entry::deserialize(&mut reader) {
+            Err(Error::ChecksumMismatch { index, offset }) => {
+                assert_eq!(1, index);
+                assert_eq!(record as u64, offset);
+            }
+            r => panic!("Unexpected result {:?}", r),
+        }
+
+        // Flip a bit of the checksum of the empty record
+        let mut corrupted = v;
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 0x80;
+
+        let mut reader = TlvReader::new(ChecksumReader::new(corrupted.as_slice()));
+        for _ in 0..3 {
+            reader.next().unwrap().unwrap();
+        }
+        assert!(matches!(
+            reader.decode::<Option<u64>>(),
+            Err(Error::ChecksumMismatch { index: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn checksum_max_record_len() {
+        // A record with a length of 8 bytes, whose highest byte is damaged
+        let mut record = vec![0xf8, 0x03, 0, 0, 0, 0, 0, 0, 0x00, 0x01, 0x02, 0x03];
+        let mut crc = checksum::Crc32c::new();
+        crc.update(record.as_slice());
+        record.extend_from_slice(&crc.finalize().to_le_bytes());
+
+        let mut reader = TlvReader::new(ChecksumReader::new(record.as_slice()));
+        assert_eq!(vec![0x01, 0x02, 0x03], reader.next().unwrap().unwrap());
+
+        record[8] = 0x0f;
+        let mut reader = TlvReader::new(ChecksumReader::new(record.as_slice()));
+        assert!(matches!(
+            reader.next_reader(),
+            Err(Error::ChecksumMismatch {
+                index: 0,
+                offset: 0
+            })
+        ));
+
+        // The limit is configurable
+        record[8] = 0x00;
+        let mut checked = ChecksumReader::new(record.as_slice());
+        checked.set_max_record_len(2);
+        let mut reader = TlvReader::new(checked);
+        assert!(matches!(
+            reader.next_reader(),
+            Err(Error::ChecksumMismatch { .. })
+        ));
+    }
+
+    /// Stream of `n` framed records of the same length, and the offsets of the records
+    fn synced(n: usize) -> (Vec<u8>, Vec<usize>) {
+        let mut writer = ChecksumWriter::new(vec![]);
+        writer.set_sync_markers(true);
+        let mut writer = TlvWriter::new(writer);
+
+        for i in 0..n {
+            writer.encode(&format!("Record {}", i)).unwrap();
+        }
+
+        let v = writer.into_inner().into_inner();
+        let len = v.len() / n;
+        (v, (0..n).map(|i| i * len).collect())
+    }
+
+    fn recovering(v: &[u8]) -> ChecksumReader<&[u8]> {
+        let mut reader = ChecksumReader::new(v);
+        reader.set_sync_markers(true);
+        reader.set_recovery(true);
+        reader
+    }
+
+    #[test]
+    fn checksum_sync_markers() {
+        let (v, offsets) = synced(10);
+        assert_eq!(&SYNC_MARKER[..], &v[..SYNC_MARKER.len()]);
+
+        let mut checked = ChecksumReader::new(v.as_slice());
+        checked.set_sync_markers(true);
+        let mut reader = TlvReader::new(checked);
+        for i in 0..10 {
+            assert_eq!(format!("Record {}", i), reader.decode::<String>().unwrap());
+        }
+        assert!(reader.next().is_none());
+
+        // Without sync markers, the first marker is not a valid record
+        let mut reader = TlvReader::new(ChecksumReader::new(v.as_slice()));
+        assert!(reader.decode::<String>().is_err());
+
+        // A corrupted payload and a corrupted length are skipped up to the next marker
+        let mut corrupted = v.clone();
+        corrupted[offsets[2] + SYNC_MARKER.len() + 4] ^= 0x10;
+        corrupted[offsets[6] + SYNC_MARKER.len() + 1] = 0xff;
+
+        let mut reader = TlvReader::new(recovering(corrupted.as_slice()));
+        let records: Vec<String> = (0..8).map(|_| reader.decode().unwrap()).collect();
+        assert!(reader.next().is_none());
+
+        let expected: Vec<String> = [0, 1, 3, 4, 5, 7, 8, 9]
+            .iter()
+            .map(|i| format!("Record {}", i))
+            .collect();
+        assert_eq!(expected, records);
+
+        let reader = reader.into_inner();
+        let skipped = vec![
+            offsets[2] as u64..offsets[3] as u64,
+            offsets[6] as u64..offsets[7] as u64,
+        ];
+        assert_eq!(skipped.as_slice(), reader.skipped());
+        assert_eq!(8, reader.records());
+    }
+
+    #[test]
+    fn checksum_recovery_truncated() {
+        let (v, offsets) = synced(4);
+
+        // A damaged marker is skipped with its record, and a truncated record ends the stream
+        let mut corrupted = v[..v.len() - 3].to_vec();
+        corrupted[offsets[1]] = 0x00;
+
+        let mut reader = TlvReader::new(recovering(corrupted.as_slice()));
+        assert_eq!("Record 0", reader.decode::<String>().unwrap());
+        assert_eq!("Record 2", reader.decode::<String>().unwrap());
+        assert!(reader.next().is_none());
+
+        let reader = reader.into_inner();
+        let skipped = vec![
+            offsets[1] as u64..offsets[2] as u64,
+            offsets[3] as u64..corrupted.len() as u64,
+        ];
+        assert_eq!(skipped.as_slice(), reader.skipped());
+    }
+
+    #[test]
+    fn checksum_recovery_garbage() {
+        let (v, offsets) = synced(3);
+
+        // The next marker may be anywhere in the skipped bytes, even across the scanned chunks
+        for len in [1, 4090, 4100, 10_000].iter().copied() {
+            let mut corrupted = v.clone();
+            corrupted.splice(offsets[1]..offsets[1], vec![0xaa; len]);
+
+            let mut reader = TlvReader::new(recovering(corrupted.as_slice()));
+            for i in 0..3 {
+                assert_eq!(format!("Record {}", i), reader.decode::<String>().unwrap());
+            }
+            assert!(reader.next().is_none());
+
+            let skipped = reader.into_inner().skipped().to_vec();
+            assert_eq!(1, skipped.len());
+            assert_eq!(offsets[1] as u64, skipped[0].start);
+            assert_eq!((offsets[1] + len) as u64, skipped[0].end);
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use crate::{Error, TlvReader, TlvWriter};
+
+use std::cmp;
+use std::io::{self, Read};
+
+/// Default maximum payload length of a chunk
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Consumes an implementation of [`io::Write`], and split a logical payload of unknown length
+/// into a sequence of bounded TLV chunks.
+///
+/// Every chunk is a regular TLV record with a non-empty payload. The logical payload is terminated
+/// by an empty record, `0xf0`, written by [`ChunkedWriter::finish`].
+pub struct ChunkedWriter<W>
+where
+    W: io::Write,
+{
+    writer: W,
+    buf: Vec<u8>,
+    chunk_size: usize,
+}
+
+impl<W> ChunkedWriter<W>
+where
+    W: io::Write,
+{
+    /// [`ChunkedWriter`] constructor, with chunks of [`DEFAULT_CHUNK_SIZE`]
+    pub fn new(writer: W) -> Self {
+        ChunkedWriter::with_chunk_size(writer, DEFAULT_CHUNK_SIZE)
+    }
+
+    /// [`ChunkedWriter`] constructor with a custom maximum payload length for the chunks
+    pub fn with_chunk_size(writer: W, chunk_size: usize) -> Self {
+        let chunk_size = cmp::max(chunk_size, 1);
+
+        ChunkedWriter {
+            writer,
+            buf: Vec::with_capacity(chunk_size),
+            chunk_size,
+        }
+    }
+
+    /// Emit the buffered bytes as a last chunk, write the terminator and return the inner writer
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.emit()?;
+        TlvWriter::bytes_len_to_writer(&mut self.writer, 0)?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+
+    fn emit(&mut self) -> Result<(), Error> {
+        if !self.buf.is_empty() {
+            TlvWriter::bytes_to_writer(&mut self.writer, self.buf.as_slice())?;
+            self.buf.clear();
+        }
+
+        Ok(())
+    }
+}
+
+impl<W> io::Write for ChunkedWriter<W>
+where
+    W: io::Write,
+{
+    /// The bytes are buffered until a chunk is full. If the buffer is empty and the provided slice
+    /// is big enough, the chunk is emitted directly from it.
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        if self.buf.is_empty() && buf.len() >= self.chunk_size {
+            TlvWriter::bytes_to_writer(&mut self.writer, &buf[..self.chunk_size])
+                .map_err(Into::<io::Error>::into)?;
+            return Ok(self.chunk_size);
+        }
+
+        let n = cmp::min(buf.len(), self.chunk_size - self.buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+
+        if self.buf.len() == self.chunk_size {
+            self.emit().map_err(Into::<io::Error>::into)?;
+        }
+
+        Ok(n)
+    }
+
+    /// Emit the buffered bytes as a chunk, and forward the call to the inner writer
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.emit().map_err(Into::<io::Error>::into)?;
+        self.writer.flush()
+    }
+}
+
+/// Consumes an implementation of [`io::Read`], and stream the logical payload written by a
+/// [`ChunkedWriter`].
+///
+/// The end of the stream is signaled once the terminator is read. The inner reader will then be
+/// pointing to the first byte after the terminator.
+pub struct ChunkedReader<R>
+where
+    R: io::Read,
+{
+    reader: R,
+    remaining: u64,
+    done: bool,
+}
+
+impl<R> ChunkedReader<R>
+where
+    R: io::Read,
+{
+    /// [`ChunkedReader`] constructor
+    pub fn new(reader: R) -> Self {
+        ChunkedReader {
+            reader,
+            remaining: 0,
+            done: false,
+        }
+    }
+
+    /// Consumes self, and return the inner reader
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Reassemble the remainder of the logical payload
+    pub fn read_to_vec(&mut self) -> Result<Vec<u8>, Error> {
+        let mut v = vec![];
+        self.read_to_end(&mut v)?;
+
+        Ok(v)
+    }
+}
+
+impl<R> io::Read for ChunkedReader<R>
+where
+    R: io::Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        while !self.done && self.remaining == 0 {
+            let len =
+                TlvReader::reader_to_tlv_len(&mut self.reader).map_err(Into::<io::Error>::into)?;
+
+            self.remaining = len as u64;
+            self.done = len == 0;
+        }
+
+        let max = cmp::min(buf.len() as u64, self.remaining) as usize;
+        if max == 0 {
+            return Ok(0);
+        }
+
+        let n = self.reader.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "The reader didnt provide enough bytes for the TLV decoding",
+            ));
+        }
+
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::io::{self, Write};
+    use std::iter;
+
+    #[test]
+    fn chunked_roundtrip() {
+        let buf: Vec<u8> = iter::repeat(())
+            .take(1000)
+            .enumerate()
+            .map(|(i, _)| i as u8)
+            .collect();
+
+        let mut writer = ChunkedWriter::with_chunk_size(vec![], 256);
+        writer.write_all(&buf[..100]).unwrap();
+        writer.write_all(&buf[100..]).unwrap();
+        let mut v = writer.finish().unwrap();
+        TlvWriter::bytes_to_writer(&mut v, b"Foo").unwrap();
+
+        // 3 full chunks, 1 partial chunk and the terminator
+        let mut chunks = TlvReader::new(v.as_slice());
+        for _ in 0..3 {
+            assert_eq!(256, chunks.next().unwrap().unwrap().len());
+        }
+        assert_eq!(232, chunks.next().unwrap().unwrap().len());
+        assert!(chunks.next().unwrap().unwrap().is_empty());
+
+        let mut reader = ChunkedReader::new(v.as_slice());
+        assert_eq!(buf, reader.read_to_vec().unwrap());
+
+        let mut tlv_reader = TlvReader::new(reader.into_inner());
+        assert_eq!(b"Foo", tlv_reader.next().unwrap().unwrap().as_slice());
+    }
+
+    #[test]
+    fn chunked_empty() {
+        let writer = ChunkedWriter::new(vec![]);
+        let v = writer.finish().unwrap();
+        assert_eq!(vec![0xf0u8], v);
+
+        let mut reader = ChunkedReader::new(v.as_slice());
+        assert!(reader.read_to_vec().unwrap().is_empty());
+    }
+
+    #[test]
+    fn chunked_truncated() {
+        let mut v = vec![];
+        TlvWriter::bytes_to_writer(&mut v, b"Hello ").unwrap();
+        TlvWriter::bytes_to_writer(&mut v, b"World!").unwrap();
+
+        let mut reader = ChunkedReader::new(v.as_slice());
+        let mut sink = vec![];
+        let err = io::copy(&mut reader, &mut sink).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+        assert_eq!(b"Hello World!", sink.as_slice());
+    }
+}
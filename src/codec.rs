@@ -0,0 +1,403 @@
+use crate::packed::PACKED_TAG;
+use crate::{Error, TlvReader, TlvWriter};
+
+use std::convert::TryFrom;
+use std::io;
+
+/// Type that can be written to a [`TlvWriter`] without going through serde.
+///
+/// The encoding is the same one produced by the [`serde::Serializer`] of the writer, except for
+/// byte arrays, that are written as a single record.
+pub trait TlvEncode {
+    /// Write self to the provided writer
+    fn encode<W: io::Write>(&self, writer: &mut TlvWriter<W>) -> Result<(), Error>;
+
+    /// Write a sequence of values of this type as a container. Numeric types override it to write
+    /// a packed sequence.
+    fn encode_seq<W: io::Write>(items: &[Self], writer: &mut TlvWriter<W>) -> Result<(), Error>
+    where
+        Self: Sized,
+    {
+        writer.write_container(|w| {
+            for item in items {
+                item.encode(w)?;
+            }
+
+            Ok(())
+        })
+    }
+}
+
+/// Type that can be read from a [`TlvReader`] without going through serde
+pub trait TlvDecode: Sized {
+    /// Read an instance from the provided reader
+    fn decode<R: io::Read>(reader: &mut TlvReader<R>) -> Result<Self, Error>;
+
+    /// Read a sequence of values of this type written by [`TlvEncode::encode_seq`]
+    fn decode_seq<R: io::Read>(reader: &mut TlvReader<R>) -> Result<Vec<Self>, Error> {
+        decode_records(reader)
+    }
+}
+
+/// Read a container of records of the same type
+fn decode_records<R: io::Read, T: TlvDecode>(reader: &mut TlvReader<R>) -> Result<Vec<T>, Error> {
+    reader.read_container(|c| {
+        let mut list = vec![];
+        while !c.is_exhausted() {
+            list.push(T::decode(c)?);
+        }
+
+        Ok(list)
+    })
+}
+
+/// Read the next record into the buffer, granting the payload fills it
+fn decode_exact<R: io::Read>(reader: &mut TlvReader<R>, buf: &mut [u8]) -> Result<(), Error> {
+    if reader.read_next_slice(buf)? != buf.len() {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The payload length doesnt match the decoded type",
+        )));
+    }
+
+    Ok(())
+}
+
+/// Sequences of numeric values are packed into a single record of consecutive little-endian values
+macro_rules! encode_packed_seq {
+    ($t:ty) => {
+        fn encode_seq<W: io::Write>(
+            items: &[Self],
+            writer: &mut TlvWriter<W>,
+        ) -> Result<(), Error> {
+            // Empty sequences are written as empty containers, as serde does
+            if items.is_empty() {
+                return writer.write_record(&[]).map(|_| ());
+            }
+
+            let mut payload = Vec::with_capacity(items.len() * std::mem::size_of::<$t>());
+            for item in items {
+                payload.extend_from_slice(&item.to_le_bytes()[..]);
+            }
+
+            writer.write_packed(payload.as_slice())
+        }
+    };
+}
+
+/// Decode both packed sequences and containers of records
+macro_rules! decode_packed_seq {
+    ($t:ty) => {
+        fn decode_seq<R: io::Read>(reader: &mut TlvReader<R>) -> Result<Vec<Self>, Error> {
+            if reader.peek_header()?.0 != PACKED_TAG {
+                return decode_records(reader);
+            }
+
+            let payload = reader.next_payload()?;
+            let values = payload.chunks_exact(std::mem::size_of::<$t>());
+            if !values.remainder().is_empty() {
+                return Err(Error::TrailingBytes(values.remainder().len()));
+            }
+
+            Ok(values
+                .map(|v| {
+                    let mut buf = [0x00u8; std::mem::size_of::<$t>()];
+                    buf.copy_from_slice(v);
+                    <$t>::from_le_bytes(buf)
+                })
+                .collect())
+        }
+    };
+}
+
+macro_rules! codec_int {
+    ($write:ident, $read:ident, $w:ty, $($t:ty),*) => {
+        $(
+            /// The payload follows the [`crate::IntEncoding`] of the writer
+            impl TlvEncode for $t {
+                fn encode<W: io::Write>(&self, writer: &mut TlvWriter<W>) -> Result<(), Error> {
+                    writer
+                        .$write(*self as $w, std::mem::size_of::<$t>())
+                        .map(|_| ())
+                }
+
+                encode_packed_seq!($t);
+            }
+
+            impl TlvDecode for $t {
+                fn decode<R: io::Read>(reader: &mut TlvReader<R>) -> Result<Self, Error> {
+                    reader.$read(std::mem::size_of::<$t>()).map(|v| v as $t)
+                }
+
+                decode_packed_seq!($t);
+            }
+        )*
+    };
+}
+
+codec_int!(write_unsigned, read_unsigned, u128, u8, u16, u32, u64, u128);
+codec_int!(write_signed, read_signed, i128, i8, i16, i32, i64, i128);
+
+/// Platform-dependent integers are encoded with the width of the portable type
+macro_rules! codec_portable {
+    ($($t:ty => $p:ty),*) => {
+        $(
+            impl TlvEncode for $t {
+                fn encode<W: io::Write>(&self, writer: &mut TlvWriter<W>) -> Result<(), Error> {
+                    (*self as $p).encode(writer)
+                }
+
+                fn encode_seq<W: io::Write>(
+                    items: &[Self],
+                    writer: &mut TlvWriter<W>,
+                ) -> Result<(), Error> {
+                    let items: Vec<$p> = items.iter().map(|i| *i as $p).collect();
+                    <$p>::encode_seq(items.as_slice(), writer)
+                }
+            }
+
+            impl TlvDecode for $t {
+                fn decode<R: io::Read>(reader: &mut TlvReader<R>) -> Result<Self, Error> {
+                    <$p>::decode(reader).and_then(portable)
+                }
+
+                fn decode_seq<R: io::Read>(reader: &mut TlvReader<R>) -> Result<Vec<Self>, Error> {
+                    <$p>::decode_seq(reader)?
+                        .into_iter()
+                        .map(portable)
+                        .collect()
+                }
+            }
+        )*
+    };
+}
+
+codec_portable!(usize => u64, isize => i64);
+
+/// Convert a portable integer to the platform-dependent type, failing if it doesn't fit
+fn portable<P, T: TryFrom<P>>(v: P) -> Result<T, Error> {
+    T::try_from(v).map_err(|_| {
+        Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The decoded value doesnt fit the integer of the target platform",
+        ))
+    })
+}
+
+macro_rules! codec_le {
+    ($($t:ty),*) => {
+        $(
+            impl TlvEncode for $t {
+                fn encode<W: io::Write>(&self, writer: &mut TlvWriter<W>) -> Result<(), Error> {
+                    writer.write_record(&self.to_le_bytes()[..]).map(|_| ())
+                }
+
+                encode_packed_seq!($t);
+            }
+
+            impl TlvDecode for $t {
+                fn decode<R: io::Read>(reader: &mut TlvReader<R>) -> Result<Self, Error> {
+                    let mut buf = [0x00u8; std::mem::size_of::<$t>()];
+                    decode_exact(reader, &mut buf)?;
+                    Ok(<$t>::from_le_bytes(buf))
+                }
+
+                decode_packed_seq!($t);
+            }
+        )*
+    };
+}
+
+codec_le!(f32, f64);
+
+macro_rules! codec_tuple {
+    ($($n:tt $t:ident),+) => {
+        impl<$($t: TlvEncode),+> TlvEncode for ($($t,)+) {
+            fn encode<W: io::Write>(&self, writer: &mut TlvWriter<W>) -> Result<(), Error> {
+                writer.write_container(|w| {
+                    $(self.$n.encode(w)?;)+
+                    Ok(())
+                })
+            }
+        }
+
+        impl<$($t: TlvDecode),+> TlvDecode for ($($t,)+) {
+            fn decode<R: io::Read>(reader: &mut TlvReader<R>) -> Result<Self, Error> {
+                reader.read_container(|c| Ok(($($t::decode(c)?,)+)))
+            }
+        }
+    };
+}
+
+codec_tuple!(0 A);
+codec_tuple!(0 A, 1 B);
+codec_tuple!(0 A, 1 B, 2 C);
+codec_tuple!(0 A, 1 B, 2 C, 3 D);
+codec_tuple!(0 A, 1 B, 2 C, 3 D, 4 E);
+codec_tuple!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F);
+codec_tuple!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G);
+codec_tuple!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G, 7 H);
+
+impl TlvEncode for bool {
+    fn encode<W: io::Write>(&self, writer: &mut TlvWriter<W>) -> Result<(), Error> {
+        writer.write_record(&[*self as u8]).map(|_| ())
+    }
+}
+
+impl TlvDecode for bool {
+    fn decode<R: io::Read>(reader: &mut TlvReader<R>) -> Result<Self, Error> {
+        let mut buf = [0x00u8];
+        decode_exact(reader, &mut buf)?;
+        Ok(buf[0] != 0)
+    }
+}
+
+impl<const N: usize> TlvEncode for [u8; N] {
+    fn encode<W: io::Write>(&self, writer: &mut TlvWriter<W>) -> Result<(), Error> {
+        writer.write_record(&self[..]).map(|_| ())
+    }
+}
+
+impl<const N: usize> TlvDecode for [u8; N] {
+    fn decode<R: io::Read>(reader: &mut TlvReader<R>) -> Result<Self, Error> {
+        let mut buf = [0x00u8; N];
+        decode_exact(reader, &mut buf)?;
+        Ok(buf)
+    }
+}
+
+impl TlvEncode for str {
+    fn encode<W: io::Write>(&self, writer: &mut TlvWriter<W>) -> Result<(), Error> {
+        writer.write_record(self.as_bytes()).map(|_| ())
+    }
+}
+
+impl TlvEncode for String {
+    fn encode<W: io::Write>(&self, writer: &mut TlvWriter<W>) -> Result<(), Error> {
+        self.as_str().encode(writer)
+    }
+}
+
+impl TlvDecode for String {
+    fn decode<R: io::Read>(reader: &mut TlvReader<R>) -> Result<Self, Error> {
+        String::from_utf8(reader.next_payload()?)
+            .map_err(|e| Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))
+    }
+}
+
+/// [`None`] is an empty container, while [`Some`] is a container of the record of the value
+impl<T: TlvEncode> TlvEncode for Option<T> {
+    fn encode<W: io::Write>(&self, writer: &mut TlvWriter<W>) -> Result<(), Error> {
+        writer.write_container(|w| match self {
+            Some(v) => v.encode(w),
+            None => Ok(()),
+        })
+    }
+}
+
+impl<T: TlvDecode> TlvDecode for Option<T> {
+    fn decode<R: io::Read>(reader: &mut TlvReader<R>) -> Result<Self, Error> {
+        reader.read_container(|r| {
+            if r.is_exhausted() {
+                return Ok(None);
+            }
+
+            T::decode(r).map(Some)
+        })
+    }
+}
+
+impl<T: TlvEncode> TlvEncode for [T] {
+    fn encode<W: io::Write>(&self, writer: &mut TlvWriter<W>) -> Result<(), Error> {
+        T::encode_seq(self, writer)
+    }
+}
+
+impl<T: TlvEncode> TlvEncode for Vec<T> {
+    fn encode<W: io::Write>(&self, writer: &mut TlvWriter<W>) -> Result<(), Error> {
+        self.as_slice().encode(writer)
+    }
+}
+
+impl<T: TlvDecode> TlvDecode for Vec<T> {
+    fn decode<R: io::Read>(reader: &mut TlvReader<R>) -> Result<Self, Error> {
+        T::decode_seq(reader)
+    }
+}
+
+impl<T: TlvEncode + ?Sized> TlvEncode for &T {
+    fn encode<W: io::Write>(&self, writer: &mut TlvWriter<W>) -> Result<(), Error> {
+        (**self).encode(writer)
+    }
+}
+
+impl<T: TlvEncode + ?Sized> TlvEncode for Box<T> {
+    fn encode<W: io::Write>(&self, writer: &mut TlvWriter<W>) -> Result<(), Error> {
+        (**self).encode(writer)
+    }
+}
+
+impl<T: TlvDecode> TlvDecode for Box<T> {
+    fn decode<R: io::Read>(reader: &mut TlvReader<R>) -> Result<Self, Error> {
+        T::decode(reader).map(Box::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn codec_roundtrip() {
+        let input = (
+            25u64,
+            -3i16,
+            true,
+            String::from("Foo"),
+            [0xabu8; 32],
+            vec![Some(1u32), None, Some(3)],
+            vec![vec![(1u8, 2.5f64)], vec![]],
+        );
+
+        let mut writer = TlvWriter::new(vec![]);
+        writer.encode(&input).unwrap();
+        let v = writer.into_inner();
+
+        let mut reader = TlvReader::new(v.as_slice());
+        assert_eq!(input, reader.decode().unwrap());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn codec_matches_serde() {
+        let input = (
+            7u16,
+            vec![String::from("Foo"), String::from("Bar")],
+            None::<i64>,
+        );
+
+        let mut writer = TlvWriter::new(vec![]);
+        writer.encode(&input).unwrap();
+        let v = writer.into_inner();
+
+        assert_eq!(to_vec(&input).unwrap(), v);
+        assert_eq!(input, from_slice(v.as_slice()).unwrap());
+    }
+
+    #[test]
+    fn codec_invalid_length() {
+        let mut writer = TlvWriter::new(vec![]);
+        writer.encode(&25u16).unwrap();
+        writer.encode(&(1u8, 2u8, 3u8)).unwrap();
+        let v = writer.into_inner();
+
+        let mut reader = TlvReader::new(v.as_slice());
+        assert!(reader.decode::<u32>().is_err());
+
+        let mut reader = TlvReader::new(&v[4..]);
+        match reader.decode::<(u8, u8)>() {
+            Err(Error::TrailingBytes(3)) => (),
+            r => panic!("Unexpected result {:?}", r),
+        }
+    }
+}
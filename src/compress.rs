@@ -0,0 +1,210 @@
+use crate::Error;
+
+use std::io;
+
+/// Upper nibble of the type of a compressed record, whose payload is the deflate stream of the
+/// original payload
+pub(crate) const COMPRESSED_TAG: u8 = 0x0d;
+
+/// Default maximum length of the decompressed payload of a record
+pub const DEFAULT_MAX_DECOMPRESSED_LEN: usize = 64 * 1024 * 1024;
+
+/// Compression level of deflate, balanced between speed and ratio
+#[cfg(feature = "compression")]
+const LEVEL: u8 = 6;
+
+/// Compress the payload, returning [`None`] if it doesn't get smaller
+#[cfg(feature = "compression")]
+pub(crate) fn compress(buf: &[u8]) -> Option<Vec<u8>> {
+    let compressed = miniz_oxide::deflate::compress_to_vec(buf, LEVEL);
+    if compressed.len() < buf.len() {
+        Some(compressed)
+    } else {
+        None
+    }
+}
+
+/// Decompress the payload of a compressed record, failing if it exceeds the provided length
+#[cfg(feature = "compression")]
+pub(crate) fn decompress(buf: &[u8], max_len: usize) -> Result<Vec<u8>, Error> {
+    use miniz_oxide::inflate::{self, TINFLStatus};
+
+    inflate::decompress_to_vec_with_limit(buf, max_len).map_err(|e| match e.status {
+        TINFLStatus::HasMoreOutput => Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The decompressed payload exceeds the maximum length",
+        )),
+
+        _ => Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The payload of the compressed record is not a valid deflate stream",
+        )),
+    })
+}
+
+/// Compressed records can't be read without the `compression` feature
+#[cfg(not(feature = "compression"))]
+pub(crate) fn decompress(_buf: &[u8], _max_len: usize) -> Result<Vec<u8>, Error> {
+    Err(Error::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Compressed records require the compression feature",
+    )))
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod tests {
+    use crate::*;
+
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Snapshot {
+        height: u64,
+        state: String,
+        #[serde(with = "crate::byte_array")]
+        root: [u8; 32],
+    }
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            height: 7,
+            state: "{\"balance\": 100}, ".repeat(200),
+            root: [0xab; 32],
+        }
+    }
+
+    #[test]
+    fn compression_roundtrip() {
+        let mut writer = TlvWriter::new(vec![]);
+        writer.set_compression(Some(64));
+        snapshot().serialize(&mut writer).unwrap();
+        writer.write_record(b"Foo").unwrap();
+        let v = writer.into_inner();
+
+        let plain = to_vec(&snapshot()).unwrap();
+        assert!(v.len() * 5 < plain.len());
+
+        let mut reader = TlvReader::new(v.as_slice());
+        assert_eq!(snapshot(), Snapshot::deserialize(&mut reader).unwrap());
+        assert_eq!(b"Foo", reader.next().unwrap().unwrap().as_slice());
+        assert!(reader.next().is_none());
+
+        // Payloads below the threshold, or that don't get smaller, are left as they are
+        let mut writer = TlvWriter::new(vec![]);
+        writer.set_compression(Some(64));
+        writer.write_record(&[0xab; 63]).unwrap();
+        writer
+            .write_record(&(0..=255).collect::<Vec<u8>>())
+            .unwrap();
+        let v = writer.into_inner();
+        assert_eq!(0xf1, v[0]);
+        assert_eq!(0xf2, v[65]);
+    }
+
+    #[test]
+    fn compression_limit() {
+        let mut writer = TlvWriter::new(vec![]);
+        writer.set_compression(Some(0));
+        let zeros = vec![0x00u8; 1024 * 1024];
+        writer.write_record(zeros.as_slice()).unwrap();
+        let v = writer.into_inner();
+        assert!(v.len() < 2048);
+        assert_eq!(0xd2, v[0]);
+
+        let mut reader = TlvReader::new(v.as_slice());
+        reader.set_max_decompressed_len(1024 * 1024 - 1);
+        assert!(reader.next().unwrap().is_err());
+
+        let mut reader = TlvReader::new(v.as_slice());
+        reader.set_max_decompressed_len(1024 * 1024);
+        assert_eq!(1024 * 1024, reader.next().unwrap().unwrap().len());
+
+        // The limit applies to the items of lists as well
+        let mut writer = TlvWriter::new(vec![]);
+        writer.write_record(v.as_slice()).unwrap();
+        let list = writer.into_inner();
+
+        let mut reader = TlvReader::new(list.as_slice());
+        reader.set_max_decompressed_len(2048);
+        assert!(reader.read_list_iter().unwrap().next().unwrap().is_err());
+
+        // The limit of a value applies to all of its decompressed payloads
+        let mut writer = TlvWriter::new(vec![]);
+        writer.set_compression(Some(0));
+        writer.write_record(&[0x00u8; 1024]).unwrap();
+        writer.write_record(&[0x00u8; 1024]).unwrap();
+        let mut list = vec![];
+        TlvWriter::bytes_to_writer(&mut list, writer.into_inner().as_slice()).unwrap();
+
+        let schema = TlvSchema::List(Box::new(TlvSchema::Bytes));
+        assert!(TlvValue::parse_with_max_decompressed_len(list.as_slice(), &schema, 2047).is_err());
+        let value =
+            TlvValue::parse_with_max_decompressed_len(list.as_slice(), &schema, 2048).unwrap();
+        assert_eq!(2, value.as_list().unwrap().len());
+
+        // A stored block whose length doesnt match its complement
+        let corrupted = [0xd1, 0x04, 0x01, 0x02, 0x03, 0x04];
+        assert!(TlvReader::new(&corrupted[..]).next().unwrap().is_err());
+    }
+
+    #[test]
+    fn compression_list_and_value() {
+        let items: Vec<u8> = (0..32)
+            .flat_map(|i| {
+                let mut writer = TlvWriter::new(vec![]);
+                writer
+                    .write_record(format!("Item {}", i % 2).as_bytes())
+                    .unwrap();
+                writer.into_inner()
+            })
+            .collect();
+
+        let mut writer = TlvWriter::new(vec![]);
+        writer.set_compression(Some(0));
+        writer.write_record(items.as_slice()).unwrap();
+        writer.write_record(b"Foo").unwrap();
+        let v = writer.into_inner();
+        assert_eq!(0xd1, v[0]);
+
+        let mut reader = TlvReader::new(v.as_slice());
+        let list = reader.read_list_iter().unwrap();
+        assert_eq!(items.len() as u64, list.remaining());
+        let list: Vec<Vec<u8>> = list.collect::<Result<_, _>>().unwrap();
+        assert_eq!(32, list.len());
+        assert_eq!(b"Item 1", list[31].as_slice());
+        assert_eq!(b"Foo", reader.next().unwrap().unwrap().as_slice());
+
+        // Compressed records are skipped as any other record
+        let mut reader = TlvReader::new(v.as_slice());
+        serde::de::IgnoredAny::deserialize(&mut reader).unwrap();
+        assert_eq!(b"Foo", reader.next().unwrap().unwrap().as_slice());
+
+        let values = TlvValue::parse_slice(v.as_slice(), 8).unwrap();
+        assert_eq!(32, values[0].as_list().unwrap().len());
+        assert_eq!(
+            TlvValue::parse(&items[..], 0).unwrap(),
+            values[0].as_list().unwrap()[0]
+        );
+    }
+
+    #[test]
+    fn compression_untrusted_len() {
+        // A compressed record that claims a payload of almost 2^60 bytes
+        let v = [0xd8, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x0f, 0x00];
+        assert!(TlvReader::new(&v[..]).next().unwrap().is_err());
+        assert!(from_slice::<String>(&v[..]).is_err());
+        assert!(from_slice::<u64>(&v[..]).is_err());
+        assert!(TlvReader::new(&v[..]).next_reader().is_err());
+        assert!(TlvValue::parse(&v[..], 8).is_err());
+
+        // The compressed payload is bounded by the configured limit as well
+        let mut writer = TlvWriter::new(vec![]);
+        writer.set_compression(Some(0));
+        writer.write_record(&[0x00u8; 1024]).unwrap();
+        let v = writer.into_inner();
+
+        let mut reader = TlvReader::new(v.as_slice());
+        reader.set_max_decompressed_len(4);
+        assert!(reader.next().unwrap().is_err());
+    }
+}
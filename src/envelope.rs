@@ -0,0 +1,222 @@
+use crate::{from_slice, Error, TlvDecode, TlvEncode, TlvReader, TlvWriter};
+
+use std::io;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Signature scheme that signs the payload of a [`SignedEnvelope`]
+pub trait Signer {
+    /// Public key that verifies the signatures of this signer
+    fn public_key(&self) -> Vec<u8>;
+
+    /// Sign the provided message
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// Signature scheme that verifies the payload of a [`SignedEnvelope`]
+pub trait Verifier {
+    /// Check if the signature of the message is valid for the public key. The verifier is also
+    /// responsible for deciding whether the public key is trusted.
+    fn verify(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> bool;
+}
+
+/// Value serialized canonically and signed, with the public key of the signer.
+///
+/// The envelope is encoded as a container of three records: the payload, the signature and the
+/// public key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedEnvelope {
+    payload: Vec<u8>,
+    signature: Vec<u8>,
+    public_key: Vec<u8>,
+}
+
+impl SignedEnvelope {
+    /// Serialize the value canonically, and sign the resulting bytes
+    pub fn sign<T, S>(value: &T, signer: &S) -> Result<Self, Error>
+    where
+        T: Serialize + ?Sized,
+        S: Signer,
+    {
+        let mut writer = TlvWriter::new(vec![]);
+        writer.set_canonical(true);
+        value.serialize(&mut writer)?;
+        let payload = writer.into_inner();
+
+        let signature = signer.sign(payload.as_slice())?;
+
+        Ok(SignedEnvelope {
+            payload,
+            signature,
+            public_key: signer.public_key(),
+        })
+    }
+
+    /// Verify the signature of the payload, and deserialize the value only if it is valid
+    pub fn open<T, V>(&self, verifier: &V) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+        V: Verifier,
+    {
+        if !verifier.verify(
+            self.public_key.as_slice(),
+            self.payload.as_slice(),
+            self.signature.as_slice(),
+        ) {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The signature of the envelope is invalid",
+            )));
+        }
+
+        from_slice(self.payload.as_slice())
+    }
+
+    /// Canonical encoding of the signed value
+    pub fn payload(&self) -> &[u8] {
+        self.payload.as_slice()
+    }
+
+    /// Signature of the payload
+    pub fn signature(&self) -> &[u8] {
+        self.signature.as_slice()
+    }
+
+    /// Public key of the signer
+    pub fn public_key(&self) -> &[u8] {
+        self.public_key.as_slice()
+    }
+}
+
+impl TlvEncode for SignedEnvelope {
+    fn encode<W: io::Write>(&self, writer: &mut TlvWriter<W>) -> Result<(), Error> {
+        writer.write_container(|w| {
+            w.write_record(self.payload.as_slice())?;
+            w.write_record(self.signature.as_slice())?;
+            w.write_record(self.public_key.as_slice())?;
+            Ok(())
+        })
+    }
+}
+
+impl TlvDecode for SignedEnvelope {
+    fn decode<R: io::Read>(reader: &mut TlvReader<R>) -> Result<Self, Error> {
+        reader.read_container(|c| {
+            Ok(SignedEnvelope {
+                payload: c.read_bytes()?,
+                signature: c.read_bytes()?,
+                public_key: c.read_bytes()?,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    use std::collections::HashMap;
+
+    use serde::{Deserialize, Serialize};
+
+    /// Deterministic signature: the key xored over the FNV-1a of the message
+    struct MockSigner(u64);
+
+    fn mock_signature(key: u64, message: &[u8]) -> Vec<u8> {
+        let mut h = 0xcbf2_9ce4_8422_2325u64;
+        for b in message {
+            h = (h ^ *b as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+
+        (h ^ key).to_le_bytes().to_vec()
+    }
+
+    impl Signer for MockSigner {
+        fn public_key(&self) -> Vec<u8> {
+            self.0.to_le_bytes().to_vec()
+        }
+
+        fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+            Ok(mock_signature(self.0, message))
+        }
+    }
+
+    struct MockVerifier;
+
+    impl Verifier for MockVerifier {
+        fn verify(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+            let mut key = [0x00u8; 8];
+            if public_key.len() != key.len() {
+                return false;
+            }
+
+            key.copy_from_slice(public_key);
+            mock_signature(u64::from_le_bytes(key), message) == signature
+        }
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Message {
+        nonce: u64,
+        balances: HashMap<String, u64>,
+    }
+
+    fn message() -> Message {
+        let balances = (0..16)
+            .map(|i| (format!("Account {}", i), i * 100))
+            .collect();
+        Message { nonce: 7, balances }
+    }
+
+    fn forge(payload: &[u8], signature: &[u8], public_key: &[u8]) -> SignedEnvelope {
+        let mut writer = TlvWriter::new(vec![]);
+        writer.begin_container().unwrap();
+        writer.write_record(payload).unwrap();
+        writer.write_record(signature).unwrap();
+        writer.write_record(public_key).unwrap();
+        writer.end_container().unwrap();
+        let v = writer.into_inner();
+
+        TlvReader::new(v.as_slice()).decode().unwrap()
+    }
+
+    #[test]
+    fn signed_envelope_roundtrip() {
+        let envelope = SignedEnvelope::sign(&message(), &MockSigner(0xabcd)).unwrap();
+        assert_eq!(
+            envelope,
+            SignedEnvelope::sign(&message(), &MockSigner(0xabcd)).unwrap()
+        );
+
+        let mut writer = TlvWriter::new(vec![]);
+        writer.encode(&envelope).unwrap();
+        let v = writer.into_inner();
+
+        let decoded: SignedEnvelope = TlvReader::new(v.as_slice()).decode().unwrap();
+        assert_eq!(envelope, decoded);
+        assert_eq!(message(), decoded.open(&MockVerifier).unwrap());
+    }
+
+    #[test]
+    fn signed_envelope_tampered() {
+        let envelope = SignedEnvelope::sign(&message(), &MockSigner(0xabcd)).unwrap();
+        let key = envelope.public_key();
+
+        let mut tampered = message();
+        tampered.nonce = 8;
+        let tampered = SignedEnvelope::sign(&tampered, &MockSigner(0xabcd)).unwrap();
+        let forged = forge(tampered.payload(), envelope.signature(), key);
+        assert!(forged.open::<Message, _>(&MockVerifier).is_err());
+
+        let other = SignedEnvelope::sign(&message(), &MockSigner(0x1234)).unwrap();
+        let forged = forge(other.payload(), other.signature(), key);
+        assert!(forged.open::<Message, _>(&MockVerifier).is_err());
+
+        let genuine = forge(envelope.payload(), envelope.signature(), key);
+        assert_eq!(
+            message(),
+            genuine.open::<Message, _>(&MockVerifier).unwrap()
+        );
+    }
+}
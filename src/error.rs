@@ -5,27 +5,35 @@ use std::io;
 use serde::de::Error as SerdeDeError;
 use serde::ser::Error as SerdeSerError;
 
-macro_rules! from_error {
-    ($t:ty, $id:ident) => {
-        impl From<$t> for Error {
-            fn from(e: $t) -> Self {
-                Error::$id(e)
-            }
-        }
-    };
-}
-
-/// Standard error for the interface
+/// Standard error for the interface.
+///
+/// New variants may be added in minor releases, so matches must include a wildcard arm.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// I/O [`io::Error`]
     Io(io::Error),
+    /// The value was decoded, but the provided amount of bytes of the input was left unconsumed
+    TrailingBytes(usize),
+    /// The checksum of a framed record didnt match its contents
+    ChecksumMismatch {
+        /// Index of the record in the stream
+        index: usize,
+        /// Offset of the first byte of the record in the stream
+        offset: u64,
+    },
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::Io(e) => write!(f, "{}", e),
+            Error::TrailingBytes(n) => write!(f, "{} trailing bytes were not consumed", n),
+            Error::ChecksumMismatch { index, offset } => write!(
+                f,
+                "The checksum of the record {} at offset {} doesnt match",
+                index, offset
+            ),
         }
     }
 }
@@ -34,6 +42,7 @@ impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
             Error::Io(e) => Some(e),
+            Error::TrailingBytes(_) | Error::ChecksumMismatch { .. } => None,
         }
     }
 }
@@ -66,8 +75,28 @@ impl Into<io::Error> for Error {
     fn into(self) -> io::Error {
         match self {
             Error::Io(e) => e,
+            e @ Error::TrailingBytes(_) => {
+                io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+            }
+
+            // Kept as the inner error, so it can be recovered by [`From<io::Error>`]
+            e @ Error::ChecksumMismatch { .. } => io::Error::new(io::ErrorKind::InvalidData, e),
         }
     }
 }
 
-from_error!(io::Error, Io);
+/// Errors of this crate that went through an [`io::Read`] or [`io::Write`] are unwrapped
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        if !matches!(e.get_ref(), Some(i) if i.is::<Error>()) {
+            return Error::Io(e);
+        }
+
+        let kind = e.kind();
+        match e.into_inner().map(|i| i.downcast::<Error>()) {
+            Some(Ok(e)) => *e,
+            Some(Err(i)) => Error::Io(io::Error::new(kind, i)),
+            None => Error::Io(kind.into()),
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use crate::size::layouts_with;
+use crate::{Error, IntEncoding, TlvEncode, TlvWriter};
+
+use std::io;
+
+use serde::Serialize;
+
+macro_rules! digest_hasher {
+    ($feature:literal, $krate:ident, $($t:ident),+) => {
+        $(
+            #[cfg(feature = $feature)]
+            impl TlvHasher for $krate::$t {
+                type Output = $krate::digest::Output<$krate::$t>;
+
+                fn update(&mut self, data: &[u8]) {
+                    $krate::Digest::update(self, data)
+                }
+
+                fn finalize(self) -> Self::Output {
+                    $krate::Digest::finalize(self)
+                }
+            }
+        )+
+    };
+}
+
+/// Hash function that can be fed incrementally with the bytes of a TLV encoding.
+///
+/// Implementations for the hashes of `sha2` and `blake2` are provided behind the features of the
+/// same name.
+pub trait TlvHasher {
+    /// Digest produced by the hash function
+    type Output;
+
+    /// Feed the provided bytes to the hash function
+    fn update(&mut self, data: &[u8]);
+
+    /// Consume the hasher and return the digest of the bytes fed to it
+    fn finalize(self) -> Self::Output;
+}
+
+digest_hasher!("sha2", sha2, Sha224, Sha256, Sha384, Sha512);
+digest_hasher!("blake2", blake2, Blake2b512, Blake2s256);
+
+/// Implementation of [`io::Write`] that feeds the written bytes to a [`TlvHasher`], instead of
+/// storing them.
+///
+/// Use it as the writer of a [`TlvWriter`] to hash values while they are serialized.
+#[derive(Debug, Default, Clone)]
+pub struct HashWriter<H> {
+    hasher: H,
+}
+
+impl<H: TlvHasher> HashWriter<H> {
+    /// Feed the written bytes to the provided hasher
+    pub fn new(hasher: H) -> Self {
+        HashWriter { hasher }
+    }
+
+    /// Return the digest of the bytes written so far
+    pub fn finalize(self) -> H::Output {
+        self.hasher.finalize()
+    }
+
+    /// Unwrap the hasher
+    pub fn into_inner(self) -> H {
+        self.hasher
+    }
+}
+
+impl<H: TlvHasher> io::Write for HashWriter<H> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.hasher.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Hash the canonical TLV encoding of the value with a default instance of the hasher.
+///
+/// The encoding is fed to the hasher as it is produced. The lengths of the containers are
+/// computed by a sizing pass in advance, so only the entries of maps and sets, that must be
+/// sorted, are buffered.
+pub fn hash_of<H, T: ?Sized>(value: &T) -> Result<H::Output, Error>
+where
+    H: TlvHasher + Default,
+    T: Serialize,
+{
+    hash_with(H::default(), value)
+}
+
+/// Hash the canonical TLV encoding of the value with the provided hasher
+pub fn hash_with<H, T: ?Sized>(hasher: H, value: &T) -> Result<H::Output, Error>
+where
+    H: TlvHasher,
+    T: Serialize,
+{
+    let layouts = layouts_with(value, IntEncoding::default(), false)?;
+
+    let mut writer = TlvWriter::new(HashWriter::new(hasher));
+    writer.set_canonical(true);
+    writer.set_layouts(layouts);
+    value.serialize(&mut writer)?;
+
+    Ok(writer.into_inner().finalize())
+}
+
+/// Hash the [`TlvEncode`] encoding of the value with a default instance of the hasher
+pub fn hash_encoded<H, T: ?Sized>(value: &T) -> Result<H::Output, Error>
+where
+    H: TlvHasher + Default,
+    T: TlvEncode,
+{
+    let mut writer = TlvWriter::new(HashWriter::new(H::default()));
+    value.encode(&mut writer)?;
+
+    Ok(writer.into_inner().finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use serde::Serialize;
+
+    use std::collections::{HashMap, HashSet};
+
+    /// FNV-1a, to test the hashing without additional dependencies
+    struct Fnv(u64);
+
+    impl Default for Fnv {
+        fn default() -> Self {
+            Fnv(0xcbf2_9ce4_8422_2325)
+        }
+    }
+
+    impl TlvHasher for Fnv {
+        type Output = u64;
+
+        fn update(&mut self, data: &[u8]) {
+            for b in data {
+                self.0 = (self.0 ^ *b as u64).wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+
+        fn finalize(self) -> u64 {
+            self.0
+        }
+    }
+
+    fn fnv(data: &[u8]) -> u64 {
+        let mut hasher = Fnv::default();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    #[test]
+    fn hash_of_matches_encoding() {
+        let value = (String::from("Foo"), vec![1u32, 2, 3], Some(-5i64));
+        assert_eq!(
+            fnv(to_vec(&value).unwrap().as_slice()),
+            hash_of::<Fnv, _>(&value).unwrap()
+        );
+        assert_eq!(
+            hash_of::<Fnv, _>(&value).unwrap(),
+            hash_with(Fnv::default(), &value).unwrap()
+        );
+
+        let mut writer = TlvWriter::new(vec![]);
+        writer.encode(&[1u32, 2, 3][..]).unwrap();
+        assert_eq!(
+            fnv(writer.into_inner().as_slice()),
+            hash_encoded::<Fnv, _>(&[1u32, 2, 3][..]).unwrap()
+        );
+
+        // Maps are hashed canonically, regardless of the iteration order
+        let a: HashMap<u16, u16> = (0..64).map(|i| (i, i * 2)).collect();
+        let mut b: HashMap<u16, u16> = HashMap::with_capacity(1024);
+        b.extend((0..64).rev().map(|i| (i, i * 2)));
+        assert_eq!(
+            hash_of::<Fnv, _>(&a).unwrap(),
+            hash_of::<Fnv, _>(&b).unwrap()
+        );
+    }
+
+    #[derive(Serialize)]
+    enum Entry {
+        Empty,
+        Note(String),
+        Tags(#[serde(with = "crate::set")] HashSet<u16>),
+        Moves { from: Option<u8>, to: Vec<u64> },
+    }
+
+    #[test]
+    fn hash_of_streamed_containers() {
+        let entries = vec![
+            Entry::Empty,
+            Entry::Note("a".repeat(300)),
+            Entry::Tags((0..32).collect()),
+            Entry::Moves {
+                from: Some(3),
+                to: (0..100).collect(),
+            },
+            Entry::Moves {
+                from: None,
+                to: vec![],
+            },
+        ];
+        let index: HashMap<String, Vec<Option<()>>> = (0..16)
+            .map(|i| (format!("Key {}", i), vec![Some(()); i]))
+            .collect();
+        let value = (entries, Some(index), b"Foo".to_vec());
+
+        let mut writer = TlvWriter::new(vec![]);
+        writer.set_canonical(true);
+        value.serialize(&mut writer).unwrap();
+        assert_eq!(
+            fnv(writer.into_inner().as_slice()),
+            hash_of::<Fnv, _>(&value).unwrap()
+        );
+    }
+
+    #[cfg(feature = "sha2")]
+    #[test]
+    fn hash_of_sha256() {
+        use sha2::Digest;
+
+        let v = to_vec(&(1u8, "Foo")).unwrap();
+        assert_eq!(
+            sha2::Sha256::digest(v.as_slice()),
+            hash_of::<sha2::Sha256, _>(&(1u8, "Foo")).unwrap()
+        );
+    }
+}
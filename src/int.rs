@@ -0,0 +1,248 @@
+use crate::Error;
+
+use std::io;
+
+/// Widest encoded integer, that is a varint of 128 bits
+const MAX_INT_LEN: usize = 19;
+
+/// Encoding of the payload of integer records, shared by a [`crate::TlvWriter`] and the
+/// [`crate::TlvReader`] that reads its output.
+///
+/// Floats, packed sequences and enum variant indexes are always fixed-width little-endian.
+/// [`crate::to_value`] and [`crate::from_value`] support only the default encoding and the
+/// regular struct layout, so records of other encodings must be read via a configured
+/// [`crate::TlvReader`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntEncoding {
+    /// Fixed-width little-endian
+    FixedLe,
+    /// Fixed-width big-endian
+    FixedBe,
+    /// LEB128 varint, with zigzag encoding for signed integers
+    Varint,
+}
+
+impl Default for IntEncoding {
+    fn default() -> Self {
+        IntEncoding::FixedLe
+    }
+}
+
+/// Payload of an encoded integer
+pub(crate) struct EncodedInt {
+    bytes: [u8; MAX_INT_LEN],
+    len: usize,
+}
+
+impl EncodedInt {
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl IntEncoding {
+    /// Encode an unsigned integer of `width` bytes
+    pub(crate) fn encode_unsigned(self, v: u128, width: usize) -> EncodedInt {
+        let mut bytes = [0x00u8; MAX_INT_LEN];
+
+        let len = match self {
+            IntEncoding::FixedLe => {
+                bytes[..width].copy_from_slice(&v.to_le_bytes()[..width]);
+                width
+            }
+
+            IntEncoding::FixedBe => {
+                bytes[..width].copy_from_slice(&v.to_be_bytes()[16 - width..]);
+                width
+            }
+
+            IntEncoding::Varint => {
+                let mut v = v;
+                let mut len = 0;
+                loop {
+                    bytes[len] = (v & 0x7f) as u8;
+                    v >>= 7;
+                    len += 1;
+
+                    if v == 0 {
+                        break len;
+                    }
+                    bytes[len - 1] |= 0x80;
+                }
+            }
+        };
+
+        EncodedInt { bytes, len }
+    }
+
+    /// Encode a signed integer of `width` bytes
+    pub(crate) fn encode_signed(self, v: i128, width: usize) -> EncodedInt {
+        match self {
+            // Zigzag maps small magnitudes to small unsigned values
+            IntEncoding::Varint => self.encode_unsigned(((v << 1) ^ (v >> 127)) as u128, width),
+
+            // Two's complement truncated to the width
+            _ => self.encode_unsigned(v as u128, width),
+        }
+    }
+
+    /// Decode an unsigned integer of `width` bytes, failing if the value doesn't fit it
+    pub(crate) fn decode_unsigned(self, buf: &[u8], width: usize) -> Result<u128, Error> {
+        let v = match self {
+            IntEncoding::FixedLe | IntEncoding::FixedBe => {
+                if buf.len() != width {
+                    return Err(Error::Io(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "The payload length doesnt match the width of the integer",
+                    )));
+                }
+
+                let mut bytes = [0x00u8; 16];
+                if self == IntEncoding::FixedLe {
+                    bytes[..width].copy_from_slice(buf);
+                    u128::from_le_bytes(bytes)
+                } else {
+                    bytes[16 - width..].copy_from_slice(buf);
+                    u128::from_be_bytes(bytes)
+                }
+            }
+
+            IntEncoding::Varint => decode_varint(buf)?,
+        };
+
+        if width < 16 && v >> (width * 8) != 0 {
+            return Err(overflow());
+        }
+
+        Ok(v)
+    }
+
+    /// Decode a signed integer of `width` bytes, failing if the value doesn't fit it
+    pub(crate) fn decode_signed(self, buf: &[u8], width: usize) -> Result<i128, Error> {
+        if self != IntEncoding::Varint {
+            // Sign-extend the two's complement of the width
+            let shift = 128 - width * 8;
+            let v = self.decode_unsigned(buf, width)?;
+            return Ok(((v << shift) as i128) >> shift);
+        }
+
+        let v = decode_varint(buf)?;
+        let v = (v >> 1) as i128 ^ -((v & 1) as i128);
+
+        let bits = width * 8;
+        if bits < 128 && (v < -(1 << (bits - 1)) || v >= 1 << (bits - 1)) {
+            return Err(overflow());
+        }
+
+        Ok(v)
+    }
+}
+
+/// Decode a LEB128 varint that must compose the whole payload
+fn decode_varint(buf: &[u8]) -> Result<u128, Error> {
+    let mut v = 0u128;
+
+    for (i, b) in buf.iter().enumerate() {
+        let shift = i * 7;
+        let bits = (*b & 0x7f) as u128;
+        if shift >= 128 || (shift > 121 && bits >> (128 - shift) != 0) {
+            return Err(overflow());
+        }
+
+        v |= bits << shift;
+
+        if b & 0x80 == 0 {
+            if i + 1 != buf.len() {
+                return Err(Error::TrailingBytes(buf.len() - i - 1));
+            }
+
+            return Ok(v);
+        }
+    }
+
+    Err(Error::Io(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "The varint is not terminated",
+    )))
+}
+
+fn overflow() -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "The decoded integer doesnt fit the target type",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Ints {
+        a: u8,
+        b: i16,
+        c: u32,
+        d: i64,
+        e: u64,
+        f: f32,
+    }
+
+    fn ints() -> Ints {
+        Ints {
+            a: 0xfe,
+            b: -300,
+            c: 1,
+            d: i64::MIN,
+            e: u64::MAX,
+            f: 1.5,
+        }
+    }
+
+    fn roundtrip(encoding: IntEncoding) -> Vec<u8> {
+        let input = ints();
+
+        let mut writer = TlvWriter::with_int_encoding(vec![], encoding);
+        input.serialize(&mut writer).unwrap();
+        let v = writer.into_inner();
+
+        let mut reader = TlvReader::with_int_encoding(v.as_slice(), encoding);
+        assert_eq!(input, Ints::deserialize(&mut reader).unwrap());
+        assert!(reader.next().is_none());
+        assert_eq!(v.len(), serialized_size_with(&input, encoding).unwrap());
+
+        v
+    }
+
+    #[test]
+    fn int_encoding_fixed() {
+        let v = roundtrip(IntEncoding::FixedLe);
+        assert_eq!(to_vec(&ints()).unwrap(), v);
+        assert_eq!(&[0xf1, 0x02, 0xd4, 0xfe], &v[5..9]);
+
+        let v = roundtrip(IntEncoding::FixedBe);
+        assert_eq!(&[0xf1, 0x02, 0xfe, 0xd4], &v[5..9]);
+    }
+
+    #[test]
+    fn int_encoding_varint() {
+        let v = roundtrip(IntEncoding::Varint);
+        assert_eq!(roundtrip(IntEncoding::FixedLe).len() + 2, v.len());
+
+        let mut writer = TlvWriter::with_int_encoding(vec![], IntEncoding::Varint);
+        writer.write_usize(1).unwrap();
+        writer.encode(&-1i32).unwrap();
+        writer.encode(&300u16).unwrap();
+        let v = writer.into_inner();
+        assert_eq!(
+            vec![0xf1, 0x01, 0x01, 0xf1, 0x01, 0x01, 0xf1, 0x02, 0xac, 0x02],
+            v
+        );
+
+        let mut reader = TlvReader::with_int_encoding(v.as_slice(), IntEncoding::Varint);
+        assert_eq!(1u64, reader.decode().unwrap());
+        assert_eq!(-1i32, reader.decode().unwrap());
+        assert!(reader.decode::<u8>().is_err());
+    }
+}
@@ -1,11 +1,39 @@
-#![feature(external_doc)]
 #![deny(missing_docs)]
-#![doc(include = "../README.md")]
+#![doc = include_str!("../README.md")]
 
+pub mod byte_array;
+mod checksum;
+mod chunked;
+mod codec;
+mod compress;
+mod envelope;
 mod error;
+mod hash;
+mod int;
+mod list;
+mod merkle;
+mod packed;
 mod reader;
+pub mod set;
+mod size;
+mod value;
 mod writer;
 
+pub use checksum::{
+    ChecksumReader, ChecksumWriter, CHECKSUM_LEN, DEFAULT_MAX_RECORD_LEN, SYNC_MARKER,
+};
+pub use chunked::{ChunkedReader, ChunkedWriter, DEFAULT_CHUNK_SIZE};
+pub use codec::{TlvDecode, TlvEncode};
+pub use compress::DEFAULT_MAX_DECOMPRESSED_LEN;
+#[cfg(feature = "derive")]
+pub use dusk_tlv_derive::{TlvDecode, TlvEncode};
+pub use envelope::{SignedEnvelope, Signer, Verifier};
 pub use error::Error;
-pub use reader::TlvReader;
-pub use writer::TlvWriter;
+pub use hash::{hash_encoded, hash_of, hash_with, HashWriter, TlvHasher};
+pub use int::IntEncoding;
+pub use list::TlvListWriter;
+pub use merkle::{MerkleProof, MerkleTree};
+pub use reader::{from_reader, from_slice, TlvListIter, TlvPayload, TlvReader};
+pub use size::{encoded_len, header_len, serialized_size, serialized_size_with};
+pub use value::{from_value, to_value, TlvSchema, TlvValue};
+pub use writer::{to_vec, to_writer, TlvRecord, TlvWriter};
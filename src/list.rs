@@ -0,0 +1,229 @@
+use crate::writer::{reserve_header, seal_header};
+use crate::{Error, TlvWriter};
+
+use std::io;
+
+enum ListBuf<'a> {
+    Owned(Vec<u8>),
+    Borrowed(&'a mut Vec<u8>),
+}
+
+/// Builder of a TLV list, provided by [`TlvWriter::begin_list`] or, for nested lists, by
+/// [`TlvListWriter::begin_list`].
+///
+/// All the levels of a list share a single buffer. Every list reserves room for the widest header
+/// when it is opened; once it is closed, the real header is written and the payload is shifted
+/// into place inside the same buffer. The outermost list writes the buffer to the inner writer.
+///
+/// The list is closed by [`TlvListWriter::finish`], or automatically when dropped. Errors when
+/// closing on drop are ignored, so [`TlvListWriter::finish`] should be preferred. A list that
+/// should not be written, such as when producing one of its items failed, is dropped via
+/// [`TlvListWriter::discard`].
+pub struct TlvListWriter<'a, W>
+where
+    W: io::Write,
+{
+    writer: Option<&'a mut W>,
+    buf: ListBuf<'a>,
+    start: usize,
+    finished: bool,
+}
+
+impl<'a, W> TlvListWriter<'a, W>
+where
+    W: io::Write,
+{
+    pub(crate) fn root(writer: &'a mut W) -> Self {
+        TlvListWriter::open(Some(writer), ListBuf::Owned(vec![]))
+    }
+
+    /// List written into the buffer of an open container, that is written once the container is
+    /// closed
+    pub(crate) fn buffered(buf: &'a mut Vec<u8>) -> Self {
+        TlvListWriter::open(None, ListBuf::Borrowed(buf))
+    }
+
+    fn open(writer: Option<&'a mut W>, mut buf: ListBuf<'a>) -> Self {
+        let b = match &mut buf {
+            ListBuf::Owned(b) => b,
+            ListBuf::Borrowed(b) => &mut **b,
+        };
+
+        let start = reserve_header(b);
+
+        TlvListWriter {
+            writer,
+            buf,
+            start,
+            finished: false,
+        }
+    }
+
+    fn buf_mut(&mut self) -> &mut Vec<u8> {
+        match &mut self.buf {
+            ListBuf::Owned(b) => b,
+            ListBuf::Borrowed(b) => b,
+        }
+    }
+
+    /// Append the provided slice of bytes to the list in TLV format
+    pub fn write_item(&mut self, item: &[u8]) -> Result<usize, Error> {
+        TlvWriter::bytes_to_writer(self.buf_mut(), item)
+    }
+
+    /// Append all the items of the provided iterator to the list in TLV format
+    pub fn extend<I>(&mut self, items: I) -> Result<usize, Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut n = 0;
+        for item in items {
+            n += self.write_item(item.as_ref())?;
+        }
+
+        Ok(n)
+    }
+
+    /// Begin a list nested into this one
+    pub fn begin_list(&mut self) -> TlvListWriter<'_, W> {
+        TlvListWriter::open(None, ListBuf::Borrowed(self.buf_mut()))
+    }
+
+    /// Close the list, and return the amount of bytes it occupies, header included
+    pub fn finish(mut self) -> Result<usize, Error> {
+        self.close()
+    }
+
+    /// Abandon the list, removing its items from the buffer. Nothing is written to the inner writer
+    pub fn discard(mut self) {
+        self.finished = true;
+
+        let start = self.start;
+        self.buf_mut().truncate(start);
+    }
+
+    fn close(&mut self) -> Result<usize, Error> {
+        self.finished = true;
+
+        let start = self.start;
+        let buf = match &mut self.buf {
+            ListBuf::Owned(b) => b,
+            ListBuf::Borrowed(b) => &mut **b,
+        };
+
+        let header_start = seal_header(buf, start);
+        let len = buf.len() - header_start;
+
+        match self.writer.as_mut() {
+            // The outermost list can be written straight from the beginning of the header
+            Some(writer) => writer.write_all(&buf[header_start..])?,
+
+            // Nested lists are shifted over the unused part of the reserved header
+            None => {
+                buf.drain(start..header_start);
+            }
+        }
+
+        Ok(len)
+    }
+}
+
+impl<'a, W> Drop for TlvListWriter<'a, W>
+where
+    W: io::Write,
+{
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn list_writer_nested() {
+        let mut writer = TlvWriter::new(vec![]);
+
+        let mut list = writer.begin_list();
+        list.write_item(b"Foo").unwrap();
+        {
+            let mut nested = list.begin_list();
+            nested.extend(vec![b"Bar", b"Baz"]).unwrap();
+            nested.begin_list().write_item(b"Deep").unwrap();
+        }
+        list.write_item(b"Qux").unwrap();
+        let n = list.finish().unwrap();
+
+        let v = writer.into_inner();
+        assert_eq!(v.len(), n);
+
+        let mut reader = TlvReader::new(v.as_slice());
+        let list = reader.read_list::<Vec<u8>>().unwrap();
+        assert_eq!(3, list.len());
+        assert_eq!(b"Foo", list[0].as_slice());
+        assert_eq!(b"Qux", list[2].as_slice());
+
+        let mut nested = TlvReader::new(list[1].as_slice());
+        assert_eq!(b"Bar", nested.next().unwrap().unwrap().as_slice());
+        assert_eq!(b"Baz", nested.next().unwrap().unwrap().as_slice());
+
+        let deep = nested.read_list::<Vec<u8>>().unwrap();
+        assert_eq!(vec![b"Deep".to_vec()], deep);
+        assert!(nested.next().is_none());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn list_writer_iter() {
+        let mut writer = TlvWriter::new(vec![]);
+        writer
+            .write_list((0..300u16).map(|i| i.to_le_bytes()))
+            .unwrap();
+
+        let v = writer.into_inner();
+
+        // 300 items of 4 bytes need a 2-byte length
+        assert_eq!(0xf2, v[0]);
+        assert_eq!(1203, v.len());
+
+        let mut reader = TlvReader::new(v.as_slice());
+        let list = reader.read_list::<Vec<u8>>().unwrap();
+        assert_eq!(300, list.len());
+        assert_eq!(&299u16.to_le_bytes()[..], list[299].as_slice());
+    }
+
+    #[test]
+    fn list_writer_discard() {
+        let mut writer = TlvWriter::new(vec![]);
+
+        let mut list = writer.begin_list();
+        list.write_item(b"Foo").unwrap();
+        {
+            let mut nested = list.begin_list();
+            nested.write_item(b"Bar").unwrap();
+            nested.discard();
+        }
+        list.write_item(b"Baz").unwrap();
+        list.finish().unwrap();
+
+        let v = writer.into_inner();
+        let list = TlvReader::new(v.as_slice()).read_list::<Vec<u8>>().unwrap();
+        assert_eq!(vec![b"Foo".to_vec(), b"Baz".to_vec()], list);
+
+        let mut writer = TlvWriter::new(vec![]);
+        writer.begin_list().discard();
+        assert!(writer.into_inner().is_empty());
+    }
+
+    #[test]
+    fn list_writer_empty() {
+        let mut writer = TlvWriter::new(vec![]);
+        writer.begin_list();
+
+        assert_eq!(vec![0xf0u8], writer.into_inner());
+    }
+}
@@ -0,0 +1,334 @@
+use crate::{Error, HashWriter, TlvDecode, TlvEncode, TlvHasher, TlvReader, TlvWriter};
+
+use std::io;
+use std::marker::PhantomData;
+
+/// Domain separation of the hashes of leaves, so a leaf can't be presented as an inner node
+const LEAF_PREFIX: u8 = 0x00;
+
+/// Domain separation of the hashes of inner nodes
+const NODE_PREFIX: u8 = 0x01;
+
+/// Domain separation of the root, that commits to the amount of items along the top node
+const ROOT_PREFIX: u8 = 0x02;
+
+/// Merkle tree over the items of a TLV list, as written by [`TlvWriter::write_list`].
+///
+/// Every leaf is the hash of the TLV record of an item, and every inner node is the hash of its
+/// two children. A node without a sibling is promoted to the next level as it is. The root is the
+/// hash of the amount of items, as 8 little-endian bytes, and the top node, that is absent for an
+/// empty list.
+#[derive(Debug, Clone)]
+pub struct MerkleTree<H> {
+    levels: Vec<Vec<Vec<u8>>>,
+    hasher: PhantomData<H>,
+}
+
+impl<H> MerkleTree<H>
+where
+    H: TlvHasher + Default,
+    H::Output: AsRef<[u8]>,
+{
+    /// Build the tree over the items that would be provided to [`TlvWriter::write_list`]
+    pub fn new<I>(items: I) -> Result<Self, Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut leaves = vec![];
+        for item in items {
+            leaves.push(leaf::<H>(item.as_ref())?);
+        }
+
+        let mut levels = vec![leaves];
+        while levels[levels.len() - 1].len() > 1 {
+            let level = levels[levels.len() - 1]
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => node::<H>(left, right),
+                    _ => pair[0].clone(),
+                })
+                .collect();
+
+            levels.push(level);
+        }
+
+        Ok(MerkleTree {
+            levels,
+            hasher: PhantomData,
+        })
+    }
+
+    /// Build the tree over the items of an encoded TLV list
+    pub fn from_list(list: &[u8]) -> Result<Self, Error> {
+        let items: Vec<Vec<u8>> = TlvReader::new(list).read_list()?;
+        MerkleTree::new(items)
+    }
+
+    /// Amount of items of the list
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    /// Check if the list has no items
+    pub fn is_empty(&self) -> bool {
+        self.levels[0].is_empty()
+    }
+
+    /// Root of the tree
+    pub fn root(&self) -> Vec<u8> {
+        let top = self.levels[self.levels.len() - 1]
+            .first()
+            .map(|t| t.as_slice())
+            .unwrap_or_default();
+
+        root_of::<H>(self.len(), top)
+    }
+
+    /// Generate the proof that the item of the provided index is part of the list
+    pub fn proof(&self, index: usize) -> Result<MerkleProof, Error> {
+        if index >= self.len() {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The index is out of the bounds of the list",
+            )));
+        }
+
+        let mut i = index;
+        let mut siblings = vec![];
+        for level in &self.levels[..self.levels.len() - 1] {
+            if let Some(sibling) = level.get(i ^ 1) {
+                siblings.push(sibling.clone());
+            }
+
+            i >>= 1;
+        }
+
+        Ok(MerkleProof {
+            index,
+            len: self.len(),
+            siblings,
+        })
+    }
+}
+
+/// Inclusion proof of an item in a list, generated by [`MerkleTree::proof`].
+///
+/// The proof is encoded as a container with the index of the item, the amount of items of the
+/// list and the hashes of the siblings from the leaf to the root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    index: usize,
+    len: usize,
+    siblings: Vec<Vec<u8>>,
+}
+
+impl MerkleProof {
+    /// Index of the item in the list
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Amount of items of the list
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if the proof is for an empty list, which is never valid
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Check if the item, as provided to [`TlvWriter::write_list`], is part of the list of the
+    /// provided root
+    pub fn verify<H>(&self, item: &[u8], root: &[u8]) -> bool
+    where
+        H: TlvHasher + Default,
+        H::Output: AsRef<[u8]>,
+    {
+        if self.index >= self.len {
+            return false;
+        }
+
+        let mut hash = match leaf::<H>(item) {
+            Ok(h) => h,
+            Err(_) => return false,
+        };
+
+        let mut i = self.index;
+        let mut n = self.len;
+        let mut siblings = self.siblings.iter();
+        while n > 1 {
+            if i ^ 1 < n {
+                hash = match siblings.next() {
+                    Some(s) if i & 1 == 0 => node::<H>(&hash, s),
+                    Some(s) => node::<H>(s, &hash),
+                    None => return false,
+                };
+            }
+
+            i >>= 1;
+            n = (n + 1) / 2;
+        }
+
+        siblings.next().is_none() && root_of::<H>(self.len, &hash) == root
+    }
+}
+
+impl TlvEncode for MerkleProof {
+    fn encode<W: io::Write>(&self, writer: &mut TlvWriter<W>) -> Result<(), Error> {
+        (self.index, self.len, &self.siblings).encode(writer)
+    }
+}
+
+impl TlvDecode for MerkleProof {
+    fn decode<R: io::Read>(reader: &mut TlvReader<R>) -> Result<Self, Error> {
+        let (index, len, siblings) = TlvDecode::decode(reader)?;
+
+        Ok(MerkleProof {
+            index,
+            len,
+            siblings,
+        })
+    }
+}
+
+/// Hash the TLV record of the item
+fn leaf<H>(item: &[u8]) -> Result<Vec<u8>, Error>
+where
+    H: TlvHasher + Default,
+    H::Output: AsRef<[u8]>,
+{
+    let mut hasher = H::default();
+    hasher.update(&[LEAF_PREFIX]);
+
+    let mut writer = HashWriter::new(hasher);
+    TlvWriter::bytes_to_writer(&mut writer, item)?;
+
+    Ok(writer.finalize().as_ref().to_vec())
+}
+
+/// Hash the amount of items along the top node, so a proof can't be presented for a list of a
+/// different length
+fn root_of<H>(len: usize, top: &[u8]) -> Vec<u8>
+where
+    H: TlvHasher + Default,
+    H::Output: AsRef<[u8]>,
+{
+    let mut hasher = H::default();
+    hasher.update(&[ROOT_PREFIX]);
+    hasher.update(&(len as u64).to_le_bytes());
+    hasher.update(top);
+
+    hasher.finalize().as_ref().to_vec()
+}
+
+fn node<H>(left: &[u8], right: &[u8]) -> Vec<u8>
+where
+    H: TlvHasher + Default,
+    H::Output: AsRef<[u8]>,
+{
+    let mut hasher = H::default();
+    hasher.update(&[NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+
+    hasher.finalize().as_ref().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    /// FNV-1a, to test the tree without additional dependencies
+    struct Fnv(u64);
+
+    impl Default for Fnv {
+        fn default() -> Self {
+            Fnv(0xcbf2_9ce4_8422_2325)
+        }
+    }
+
+    impl TlvHasher for Fnv {
+        type Output = [u8; 8];
+
+        fn update(&mut self, data: &[u8]) {
+            for b in data {
+                self.0 = (self.0 ^ *b as u64).wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+
+        fn finalize(self) -> [u8; 8] {
+            self.0.to_le_bytes()
+        }
+    }
+
+    fn items(n: usize) -> Vec<Vec<u8>> {
+        (0..n)
+            .map(|i| format!("Transaction {}", i).into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn merkle_proofs() {
+        for n in 1..12 {
+            let items = items(n);
+            let tree = MerkleTree::<Fnv>::new(&items).unwrap();
+            let root = tree.root();
+
+            let mut writer = TlvWriter::new(vec![]);
+            writer.write_list(&items).unwrap();
+            let list = writer.into_inner();
+            assert_eq!(
+                root,
+                MerkleTree::<Fnv>::from_list(list.as_slice())
+                    .unwrap()
+                    .root()
+            );
+
+            for (i, item) in items.iter().enumerate() {
+                let proof = tree.proof(i).unwrap();
+                assert!(proof.verify::<Fnv>(item, root.as_slice()));
+                assert!(!proof.verify::<Fnv>(b"Forged", root.as_slice()));
+
+                let mut writer = TlvWriter::new(vec![]);
+                writer.encode(&proof).unwrap();
+                let v = writer.into_inner();
+                let decoded: MerkleProof = TlvReader::new(v.as_slice()).decode().unwrap();
+                assert_eq!(proof, decoded);
+                assert!(decoded.verify::<Fnv>(item, root.as_slice()));
+
+                if n > 1 {
+                    let other = (i + 1) % n;
+                    assert!(!proof.verify::<Fnv>(&items[other], root.as_slice()));
+                }
+            }
+
+            assert!(tree.proof(n).is_err());
+        }
+
+        let tree = MerkleTree::<Fnv>::new(Vec::<Vec<u8>>::new()).unwrap();
+        assert!(tree.is_empty());
+        let mut empty = Fnv::default();
+        empty.update(&[0x02, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(empty.finalize().to_vec(), tree.root());
+        assert!(tree.proof(0).is_err());
+    }
+
+    #[test]
+    fn merkle_proof_forged_position() {
+        // The last item of 3 has the same path to the top node as the second item of 2
+        let items = items(3);
+        let tree = MerkleTree::<Fnv>::new(&items).unwrap();
+        let root = tree.root();
+        let proof = tree.proof(2).unwrap();
+        assert!(proof.verify::<Fnv>(&items[2], root.as_slice()));
+
+        let forged = MerkleProof {
+            index: 1,
+            len: 2,
+            siblings: proof.siblings.clone(),
+        };
+        assert!(!forged.verify::<Fnv>(&items[2], root.as_slice()));
+    }
+}
@@ -0,0 +1,411 @@
+use crate::set::SET_ELEMENT;
+use crate::Error;
+
+use std::error;
+use std::fmt;
+use std::io;
+
+use serde::de::{DeserializeSeed, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Error as _, Impossible, Serialize, Serializer};
+
+/// Upper nibble of the type of a packed sequence, whose payload is composed by the consecutive
+/// little-endian bytes of numeric values of the same type
+pub(crate) const PACKED_TAG: u8 = 0x0e;
+
+macro_rules! probe_le {
+    ($m:ident, $t:ty, $k:ident) => {
+        fn $m(self, v: $t) -> Result<Self::Ok, Self::Error> {
+            Ok(Packed::new(&v.to_le_bytes()[..], Numeric::$k))
+        }
+    };
+}
+
+macro_rules! probe_unpackable {
+    ($($m:ident($($t:ty),*)),*) => {
+        $(
+            fn $m(self, $(_: $t),*) -> Result<Self::Ok, Self::Error> {
+                Err(Unpackable)
+            }
+        )*
+    };
+}
+
+macro_rules! packed_de_le {
+    ($m:ident, $v:ident, $t:ty) => {
+        fn $m<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+        where
+            V: Visitor<'de>,
+        {
+            let mut buf = [0x00u8; std::mem::size_of::<$t>()];
+            self.take(&mut buf)?;
+            visitor.$v(<$t>::from_le_bytes(buf))
+        }
+    };
+}
+
+/// Kind of a packed numeric value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Numeric {
+    Signed,
+    Unsigned,
+    Float,
+}
+
+/// Little-endian bytes of an element of a packed sequence
+pub(crate) struct Packed {
+    bytes: [u8; 8],
+    width: usize,
+    kind: Numeric,
+}
+
+impl Packed {
+    fn new(v: &[u8], kind: Numeric) -> Self {
+        let mut bytes = [0x00u8; 8];
+        bytes[..v.len()].copy_from_slice(v);
+
+        Packed {
+            bytes,
+            width: v.len(),
+            kind,
+        }
+    }
+
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.width]
+    }
+
+    fn le_bytes<const N: usize>(&self) -> [u8; N] {
+        let mut bytes = [0x00u8; N];
+        bytes.copy_from_slice(&self.bytes[..N]);
+
+        bytes
+    }
+}
+
+/// A packed element is serialized back as the numeric value it was packed from
+impl Serialize for Packed {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match (self.kind, self.width) {
+            (Numeric::Signed, 1) => serializer.serialize_i8(i8::from_le_bytes(self.le_bytes())),
+            (Numeric::Signed, 2) => serializer.serialize_i16(i16::from_le_bytes(self.le_bytes())),
+            (Numeric::Signed, 4) => serializer.serialize_i32(i32::from_le_bytes(self.le_bytes())),
+            (Numeric::Signed, 8) => serializer.serialize_i64(i64::from_le_bytes(self.le_bytes())),
+            (Numeric::Unsigned, 1) => serializer.serialize_u8(self.bytes[0]),
+            (Numeric::Unsigned, 2) => serializer.serialize_u16(u16::from_le_bytes(self.le_bytes())),
+            (Numeric::Unsigned, 4) => serializer.serialize_u32(u32::from_le_bytes(self.le_bytes())),
+            (Numeric::Unsigned, 8) => serializer.serialize_u64(u64::from_le_bytes(self.le_bytes())),
+            (Numeric::Float, 4) => serializer.serialize_f32(f32::from_le_bytes(self.le_bytes())),
+            (Numeric::Float, 8) => serializer.serialize_f64(f64::from_le_bytes(self.le_bytes())),
+            _ => Err(S::Error::custom("Invalid width of a packed value")),
+        }
+    }
+}
+
+/// Split the payload of a packed sequence into its elements, so they can be serialized again as
+/// records
+pub(crate) fn unpack(
+    payload: &[u8],
+    width: usize,
+    kind: Numeric,
+) -> impl Iterator<Item = Packed> + '_ {
+    payload.chunks(width).map(move |v| Packed::new(v, kind))
+}
+
+/// Encoding of an element of a sequence
+pub(crate) enum Element {
+    /// Bytes to be appended to the payload of the packed sequence
+    Packed(Packed),
+    /// The element is serialized as a record
+    Record,
+    /// The element can't be packed along the previous ones, of the provided width and kind. They
+    /// must be unpacked into records, and then the element is serialized as a record.
+    Unpack(usize, Numeric),
+}
+
+/// Encoding of a sequence, decided by its first element
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Packing {
+    Undecided,
+    Packed(usize, Numeric),
+    Records,
+}
+
+impl Packing {
+    /// Return the encoding of the element. A sequence is packed as long as its elements are
+    /// numeric values of the same type, and falls back to records otherwise.
+    pub(crate) fn pack<T: ?Sized>(&mut self, value: &T) -> Element
+    where
+        T: Serialize,
+    {
+        match *self {
+            Packing::Records => Element::Record,
+
+            Packing::Undecided => match value.serialize(Probe) {
+                Ok(p) => {
+                    *self = Packing::Packed(p.width, p.kind);
+                    Element::Packed(p)
+                }
+
+                Err(Unpackable) => {
+                    *self = Packing::Records;
+                    Element::Record
+                }
+            },
+
+            Packing::Packed(width, kind) => match value.serialize(Probe) {
+                Ok(p) if p.width == width && p.kind == kind => Element::Packed(p),
+                _ => {
+                    *self = Packing::Records;
+                    Element::Unpack(width, kind)
+                }
+            },
+        }
+    }
+
+    pub(crate) fn is_packed(&self) -> bool {
+        matches!(self, Packing::Packed(..))
+    }
+}
+
+/// The value is not a numeric primitive
+#[derive(Debug)]
+struct Unpackable;
+
+impl fmt::Display for Unpackable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The value cannot be packed")
+    }
+}
+
+impl error::Error for Unpackable {}
+
+impl serde::ser::Error for Unpackable {
+    fn custom<T>(_msg: T) -> Self
+    where
+        T: fmt::Display,
+    {
+        Unpackable
+    }
+}
+
+/// [`Serializer`] that returns the bytes of numeric primitives, and fails for any other value
+struct Probe;
+
+impl Serializer for Probe {
+    type Ok = Packed;
+    type Error = Unpackable;
+    type SerializeSeq = Impossible<Packed, Unpackable>;
+    type SerializeTuple = Impossible<Packed, Unpackable>;
+    type SerializeTupleStruct = Impossible<Packed, Unpackable>;
+    type SerializeTupleVariant = Impossible<Packed, Unpackable>;
+    type SerializeMap = Impossible<Packed, Unpackable>;
+    type SerializeStruct = Impossible<Packed, Unpackable>;
+    type SerializeStructVariant = Impossible<Packed, Unpackable>;
+
+    probe_le!(serialize_i8, i8, Signed);
+    probe_le!(serialize_i16, i16, Signed);
+    probe_le!(serialize_i32, i32, Signed);
+    probe_le!(serialize_i64, i64, Signed);
+    probe_le!(serialize_u8, u8, Unsigned);
+    probe_le!(serialize_u16, u16, Unsigned);
+    probe_le!(serialize_u32, u32, Unsigned);
+    probe_le!(serialize_u64, u64, Unsigned);
+    probe_le!(serialize_f32, f32, Float);
+    probe_le!(serialize_f64, f64, Float);
+
+    // 128-bit integers are not supported by the writer, so they are not packed either
+    probe_unpackable!(
+        serialize_i128(i128),
+        serialize_u128(u128),
+        serialize_bool(bool),
+        serialize_char(char),
+        serialize_str(&str),
+        serialize_bytes(&[u8]),
+        serialize_none(),
+        serialize_unit(),
+        serialize_unit_struct(&'static str),
+        serialize_unit_variant(&'static str, u32, &'static str)
+    );
+
+    fn serialize_some<T: ?Sized>(self, _value: &T) -> Result<Self::Ok, Self::Error>
+    where
+        T: Serialize,
+    {
+        Err(Unpackable)
+    }
+
+    /// The elements of sets are never packed, so they can be sorted as records
+    fn serialize_newtype_struct<T: ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error>
+    where
+        T: Serialize,
+    {
+        if name == SET_ELEMENT {
+            return Err(Unpackable);
+        }
+
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Self::Ok, Self::Error>
+    where
+        T: Serialize,
+    {
+        Err(Unpackable)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        Err(Unpackable)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        Err(Unpackable)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        Err(Unpackable)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        Err(Unpackable)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        Err(Unpackable)
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        Err(Unpackable)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        Err(Unpackable)
+    }
+}
+
+/// Provide the values of the payload of a packed sequence to the visitor, granting the payload is
+/// fully consumed.
+pub(crate) fn visit_packed<'de, V>(buf: &[u8], visitor: V) -> Result<V::Value, Error>
+where
+    V: Visitor<'de>,
+{
+    let mut seq = PackedSeq { buf };
+
+    let value = visitor.visit_seq(&mut seq)?;
+    if !seq.buf.is_empty() {
+        return Err(Error::TrailingBytes(seq.buf.len()));
+    }
+
+    Ok(value)
+}
+
+/// Sequential access to the values of a packed sequence
+struct PackedSeq<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> PackedSeq<'a> {
+    fn take(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        if self.buf.len() < buf.len() {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "The packed sequence doesnt contain enough bytes for the value",
+            )));
+        }
+
+        let (value, rest) = self.buf.split_at(buf.len());
+        buf.copy_from_slice(value);
+        self.buf = rest;
+
+        Ok(())
+    }
+}
+
+impl<'de, 'a> SeqAccess<'de> for PackedSeq<'a> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+
+        seed.deserialize(self).map(Some)
+    }
+}
+
+impl<'de, 'a, 'b> Deserializer<'de> for &'b mut PackedSeq<'a> {
+    type Error = Error;
+
+    /// Only numeric values are packed
+    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Packed sequences contain only numeric values",
+        )))
+    }
+
+    packed_de_le!(deserialize_i8, visit_i8, i8);
+    packed_de_le!(deserialize_i16, visit_i16, i16);
+    packed_de_le!(deserialize_i32, visit_i32, i32);
+    packed_de_le!(deserialize_i64, visit_i64, i64);
+    packed_de_le!(deserialize_u8, visit_u8, u8);
+    packed_de_le!(deserialize_u16, visit_u16, u16);
+    packed_de_le!(deserialize_u32, visit_u32, u32);
+    packed_de_le!(deserialize_u64, visit_u64, u64);
+    packed_de_le!(deserialize_f32, visit_f32, f32);
+    packed_de_le!(deserialize_f64, visit_f64, f64);
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool char str string bytes byte_buf option unit unit_struct seq tuple tuple_struct map
+        struct enum identifier ignored_any
+    }
+}
@@ -1,18 +1,80 @@
-use crate::Error;
+use crate::compress::{decompress, COMPRESSED_TAG, DEFAULT_MAX_DECOMPRESSED_LEN};
+use crate::packed::{visit_packed, PACKED_TAG};
+use crate::set::SET_ELEMENT;
+use crate::{Error, IntEncoding, TlvDecode};
 
+use std::cmp;
 use std::convert::TryFrom;
 use std::io::{self, Read};
 
-use serde::de::{DeserializeSeed, Deserializer, SeqAccess, Visitor};
+use serde::de::value::U32Deserializer;
+use serde::de::{
+    DeserializeOwned, DeserializeSeed, Deserializer, EnumAccess, IntoDeserializer, MapAccess,
+    SeqAccess, VariantAccess, Visitor,
+};
+
+macro_rules! read_int {
+    ($m:ident, $t:ty) => {
+        #[doc = concat!("Read the next record as a [`", stringify!($t), "`]")]
+        pub fn $m(&mut self) -> Result<$t, Error> {
+            self.decode()
+        }
+    };
+}
+
+/// Deserialize a single value from the provided reader.
+///
+/// The reader will be pointing to the first byte after the value, so any remaining bytes are left
+/// for the caller.
+pub fn from_reader<R, T>(reader: R) -> Result<T, Error>
+where
+    R: io::Read,
+    T: DeserializeOwned,
+{
+    T::deserialize(&mut TlvReader::new(reader))
+}
+
+/// Deserialize a value from the provided slice of bytes.
+///
+/// Will fail with [`Error::TrailingBytes`] if the slice is not fully consumed by the value.
+pub fn from_slice<T>(buf: &[u8]) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    let mut reader = TlvReader::new(buf);
+    let value = T::deserialize(&mut reader)?;
+    reader.check_trailing()?;
+
+    Ok(value)
+}
 
 /// Optionally consumes an implementation of [`Read`], and fetch n payloads in TLV format from it.
 ///
 /// The payloads can be fetched either via [`TlvReader::reader_to_tlv_len`], or via the iterator
+///
+/// Containers are read directly from the inner reader. While a container is open, the reader is
+/// bounded by the amount of bytes of it that were not yet consumed.
+///
+/// Integer records are decoded according to the [`IntEncoding`] of the reader, and structs
+/// according to [`TlvReader::set_compact_structs`]. Both must match the settings of the writer.
+///
+/// A strict reader, enabled by [`TlvReader::set_strict`], accepts only the canonical encoding of
+/// maps and sets, whose keys are sorted by their encoded bytes without duplicates.
 pub struct TlvReader<R>
 where
     R: io::Read,
 {
-    reader: R,
+    reader: Tap<R>,
+    remaining: u64,
+    peeked: Option<(u8, usize)>,
+    limit: Option<u64>,
+    ints: IntEncoding,
+    compact: bool,
+    fields: bool,
+    field: bool,
+    strict: bool,
+    last_key: Option<Vec<u8>>,
+    max_decompressed: usize,
 }
 
 impl<R> TlvReader<R>
@@ -21,12 +83,54 @@ where
 {
     /// [`TlvReader`] constructor
     pub fn new(reader: R) -> Self {
-        TlvReader { reader }
+        TlvReader::with_int_encoding(reader, IntEncoding::default())
+    }
+
+    /// [`TlvReader`] constructor with the provided encoding of integers
+    pub fn with_int_encoding(reader: R, ints: IntEncoding) -> Self {
+        TlvReader {
+            reader: Tap {
+                reader,
+                capture: None,
+            },
+            remaining: 0,
+            peeked: None,
+            limit: None,
+            ints,
+            compact: false,
+            fields: false,
+            field: false,
+            strict: false,
+            last_key: None,
+            max_decompressed: DEFAULT_MAX_DECOMPRESSED_LEN,
+        }
+    }
+
+    /// Maximum length of the decompressed payload of a compressed record, written by a
+    /// [`crate::TlvWriter`] with compression. Defaults to [`DEFAULT_MAX_DECOMPRESSED_LEN`].
+    #[cfg(feature = "compression")]
+    pub fn set_max_decompressed_len(&mut self, max_len: usize) {
+        self.max_decompressed = max_len;
+    }
+
+    /// Reject maps and sets that are not canonically encoded
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Read structs in the compact layout of [`crate::TlvWriter::set_compact_structs`]
+    pub fn set_compact_structs(&mut self, compact: bool) {
+        self.compact = compact;
+    }
+
+    /// Encoding of the integers read by this reader
+    pub fn int_encoding(&self) -> IntEncoding {
+        self.ints
     }
 
     /// Consumes self, and return the inner reader
     pub fn into_inner(self) -> R {
-        self.reader
+        self.reader.reader
     }
 
     /// Consumes an implementation of [`Read`], and return the amount of bytes that should be read
@@ -35,6 +139,12 @@ where
     /// The function will effectively read the bytes to fetch the length, so the reader will be
     /// pointing to the begining of the payload after the call.
     pub fn reader_to_tlv_len(reader: R) -> Result<usize, Error> {
+        TlvReader::reader_to_type_len(reader).map(|(_, l)| l)
+    }
+
+    /// Read the type and length of the next record, and return the upper nibble of the type with
+    /// the length of the payload
+    fn reader_to_type_len(reader: R) -> Result<(u8, usize), Error> {
         let mut reader = reader;
 
         // The first byte defines the type
@@ -45,6 +155,12 @@ where
         //
         // This can be performed by a simple bitwise operation
         let len = (tlv_type[0] & 0x0f) as usize;
+        if len > 8 {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The TLV length cannot be composed by more than 8 bytes",
+            )));
+        }
 
         // The TLV length cannot be bigger than a [`u64`]. Since this value is immensely big, there
         // should be no case when we need more bytes than that.
@@ -54,7 +170,7 @@ where
         reader.read_exact(&mut tlv_len[..len])?;
         let tlv_len = u64::from_le_bytes(tlv_len);
 
-        Ok(tlv_len as usize)
+        Ok((tlv_type[0] >> 4, tlv_len as usize))
     }
 
     /// From an implementation of [`Read`], fetch the type, length and write the value to the
@@ -63,7 +179,11 @@ where
         let mut reader = reader;
 
         let tlv_len = TlvReader::reader_to_tlv_len(&mut reader)?;
+        TlvReader::read_payload(reader, tlv_len, buf)
+    }
 
+    /// Write the payload of the provided length from the reader to the buf
+    fn read_payload(reader: R, tlv_len: usize, buf: &mut [u8]) -> Result<usize, Error> {
         // If the provided length is bigger than the buffer, then the provided buffer cannot
         // contain all the bytes. This verification prevents inconsistent data.
         if buf.len() < tlv_len as usize {
@@ -80,36 +200,407 @@ where
         Ok(tlv_len)
     }
 
-    /// Read a list of serializable items from the provided reader
-    pub fn read_list<L: From<Vec<u8>>>(&mut self) -> Result<Vec<L>, Error> {
-        let buf = self.next().ok_or(Error::Io(io::Error::new(
+    /// Fetch the type and length of the next record, and return a length-limited [`Read`] over its
+    /// payload, so it can be streamed without being loaded in memory.
+    ///
+    /// The returned [`TlvPayload`] must be fully consumed, or explicitly skipped via
+    /// [`TlvPayload::skip`], before the next record is fetched. Otherwise, the following reads
+    /// will fail.
+    ///
+    /// Compressed records are decompressed in memory, since the length of the decompressed
+    /// payload is not known in advance.
+    ///
+    /// Will return `Ok(None)` if there are no more records to be read, and fail if the stream ends
+    /// in the middle of a header.
+    pub fn next_reader(&mut self) -> Result<Option<TlvPayload<'_, R>>, Error> {
+        let (tag, tlv_len) = match self.next_header() {
+            Ok(h) => h,
+            Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        if tag == COMPRESSED_TAG {
+            let payload = self.read_compressed(tlv_len, self.max_decompressed)?;
+            return Ok(Some(TlvPayload {
+                tlv: self,
+                inflated: Some(io::Cursor::new(payload)),
+            }));
+        }
+
+        self.remaining = tlv_len as u64;
+        Ok(Some(TlvPayload {
+            tlv: self,
+            inflated: None,
+        }))
+    }
+
+    /// Read the next payload into the provided buf, granting the previous payload was consumed.
+    pub(crate) fn read_next_slice(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let (tag, tlv_len) = self.next_header()?;
+        if tag != COMPRESSED_TAG {
+            return TlvReader::read_payload(&mut self.reader, tlv_len, buf);
+        }
+
+        let payload = self.read_compressed(tlv_len, cmp::min(self.max_decompressed, buf.len()))?;
+        buf[..payload.len()].copy_from_slice(payload.as_slice());
+
+        Ok(payload.len())
+    }
+
+    /// Read the compressed payload of the provided length, and decompress it up to the provided
+    /// length. A record is compressed only if it gets smaller, so a compressed payload longer
+    /// than the limit is rejected before it is read.
+    fn read_compressed(&mut self, tlv_len: usize, max_len: usize) -> Result<Vec<u8>, Error> {
+        if tlv_len > max_len {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The compressed payload exceeds the maximum length",
+            )));
+        }
+
+        let mut compressed = vec![0x00u8; tlv_len];
+        self.reader.read_exact(compressed.as_mut_slice())?;
+
+        decompress(compressed.as_slice(), max_len)
+    }
+
+    /// Read the next record as a [`usize`] written by [`crate::TlvWriter::write_usize`].
+    ///
+    /// Will fail if the value doesn't fit the [`usize`] of the target platform.
+    pub fn read_usize(&mut self) -> Result<usize, Error> {
+        self.decode()
+    }
+
+    /// Read the next record as an [`isize`] written by [`crate::TlvWriter::write_isize`].
+    ///
+    /// Will fail if the value doesn't fit the [`isize`] of the target platform.
+    pub fn read_isize(&mut self) -> Result<isize, Error> {
+        self.decode()
+    }
+
+    read_int!(read_u8, u8);
+    read_int!(read_u16, u16);
+    read_int!(read_u32, u32);
+    read_int!(read_u64, u64);
+    read_int!(read_i8, i8);
+    read_int!(read_i16, i16);
+    read_int!(read_i32, i32);
+    read_int!(read_i64, i64);
+
+    /// Read the payload of the next record, written by [`crate::TlvWriter::write_record`]
+    pub fn read_bytes(&mut self) -> Result<Vec<u8>, Error> {
+        self.next_payload()
+    }
+
+    /// Read the next record as an unsigned integer of `width` bytes, following the encoding of the
+    /// reader
+    pub(crate) fn read_unsigned(&mut self, width: usize) -> Result<u128, Error> {
+        let mut buf = [0x00u8; 19];
+        let n = self.read_int(&mut buf, width)?;
+
+        self.ints.decode_unsigned(&buf[..n], width)
+    }
+
+    /// Read the next record as a signed integer of `width` bytes, following the encoding of the
+    /// reader
+    pub(crate) fn read_signed(&mut self, width: usize) -> Result<i128, Error> {
+        let mut buf = [0x00u8; 19];
+        let n = self.read_int(&mut buf, width)?;
+
+        self.ints.decode_signed(&buf[..n], width)
+    }
+
+    /// Read the payload of an integer of `width` bytes, and return its length. Varints are never
+    /// fixed-size.
+    fn read_int(&mut self, buf: &mut [u8], width: usize) -> Result<usize, Error> {
+        match self.ints {
+            IntEncoding::Varint => self.read_primitive(buf, false),
+            _ => self.read_primitive(&mut buf[..width], true),
+        }
+    }
+
+    /// Read the payload of a primitive into the buffer, and return its length.
+    ///
+    /// If the primitive is fixed-size and a field of a compact struct, it has no header, so
+    /// exactly the length of the buffer is read.
+    fn read_primitive(&mut self, buf: &mut [u8], fixed: bool) -> Result<usize, Error> {
+        if !(fixed && self.field) {
+            return self.read_next_slice(buf);
+        }
+
+        self.field = false;
+        self.check_consumed()?;
+
+        let len = buf.len() as u64;
+        if let Some(limit) = self.limit.as_mut() {
+            if len > *limit {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "The field exceeds the length of its container",
+                )));
+            }
+
+            *limit -= len;
+        }
+
+        self.reader.read_exact(buf)?;
+        Ok(buf.len())
+    }
+
+    /// Fetch the length of the next record, granting the previous payload was consumed.
+    fn next_len(&mut self) -> Result<usize, Error> {
+        self.next_header().map(|(_, l)| l)
+    }
+
+    /// Fetch the upper nibble of the type and the length of the next record, granting the
+    /// previous payload was consumed.
+    fn next_header(&mut self) -> Result<(u8, usize), Error> {
+        self.check_consumed()?;
+        self.field = false;
+
+        match self.peeked.take() {
+            Some(h) => Ok(h),
+            None => self.read_header(),
+        }
+    }
+
+    /// Read the type and length of the next record from the inner reader, and deduct the record
+    /// from the open container.
+    fn read_header(&mut self) -> Result<(u8, usize), Error> {
+        if self.limit == Some(0) {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "There are no more records in the container",
+            )));
+        }
+
+        let mut counter = Counter {
+            reader: &mut self.reader,
+            n: 0,
+        };
+        let (tag, tlv_len) = match TlvReader::reader_to_type_len(&mut counter) {
+            Ok(h) => h,
+
+            // A header cut in the middle is not the clean end of the stream
+            Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof && counter.n > 0 => {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "The TLV header is truncated",
+                )))
+            }
+            Err(e) => return Err(e),
+        };
+        let record_len = counter.n + tlv_len as u64;
+
+        if let Some(limit) = self.limit.as_mut() {
+            if record_len > *limit {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "The record exceeds the length of its container",
+                )));
+            }
+
+            *limit -= record_len;
+        }
+
+        Ok((tag, tlv_len))
+    }
+
+    /// Fetch the upper nibble of the type and the length of the next record, without consuming it
+    pub(crate) fn peek_header(&mut self) -> Result<(u8, usize), Error> {
+        let header = self.next_header()?;
+        self.peeked = Some(header);
+
+        Ok(header)
+    }
+
+    /// Read the payload of the next record, failing if there are no more records
+    pub fn next_payload(&mut self) -> Result<Vec<u8>, Error> {
+        self.next().ok_or(Error::Io(io::Error::new(
             io::ErrorKind::UnexpectedEof,
-            "Not enough bytes to read the list from the TLV format",
-        )))??;
+            "Not enough bytes to read the payload from the TLV format",
+        )))?
+    }
+
+    /// Read the next record as a container, and provide `f` with this reader bounded by the
+    /// payload of the container.
+    ///
+    /// The container must be fully consumed by `f`, otherwise [`Error::TrailingBytes`] is returned.
+    pub fn read_container<T, F>(&mut self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Self) -> Result<T, Error>,
+    {
+        let tlv_len = self.next_len()?;
+
+        let outer = self.limit.replace(tlv_len as u64);
+        let fields = std::mem::replace(&mut self.fields, false);
+        let last_key = self.last_key.take();
+        let value = f(self);
+        let left = self.limit.unwrap_or(0) + self.remaining;
+        self.limit = outer;
+        self.fields = fields;
+        self.last_key = last_key;
+
+        let value = value?;
+        if left > 0 || self.peeked.is_some() {
+            return Err(Error::TrailingBytes(left as usize));
+        }
+
+        Ok(value)
+    }
+
+    /// Read a key of the innermost container. If the reader is strict, the encoded key must be
+    /// greater than the previous one.
+    fn read_key<T, F>(&mut self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Self) -> Result<T, Error>,
+    {
+        if !self.strict {
+            return f(self);
+        }
+
+        let outer = self.reader.capture.replace(vec![]);
+        let value = f(self);
+        let key = std::mem::replace(&mut self.reader.capture, outer).unwrap_or_default();
 
-        let mut list = vec![];
-        for item in TlvReader::new(buf.as_slice()) {
-            let item = item?;
-            list.push(L::from(item));
+        // Keys nested into a key are part of the outer capture as well
+        if let Some(outer) = self.reader.capture.as_mut() {
+            outer.extend_from_slice(key.as_slice());
         }
 
-        Ok(list)
+        let value = value?;
+        if let Some(last) = self.last_key.as_ref() {
+            if key.as_slice() <= last.as_slice() {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "The keys of the container are not sorted, or are duplicated",
+                )));
+            }
+        }
+
+        self.last_key = Some(key);
+        Ok(value)
+    }
+
+    /// Read the next record as the container of the fields of a struct or tuple
+    fn read_fields<T, F>(&mut self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Self) -> Result<T, Error>,
+    {
+        self.read_container(|c| {
+            c.fields = c.compact;
+            f(c)
+        })
+    }
+
+    /// Check if all the records of the innermost open container were consumed.
+    ///
+    /// Will always return `false` if there is no open container.
+    pub fn is_exhausted(&self) -> bool {
+        self.limit == Some(0) && self.remaining == 0 && self.peeked.is_none()
+    }
+
+    fn check_consumed(&self) -> Result<(), Error> {
+        if self.remaining > 0 {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The previous payload was not fully consumed",
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Read the next value via its [`TlvDecode`] implementation
+    pub fn decode<T: TlvDecode>(&mut self) -> Result<T, Error> {
+        T::decode(self)
+    }
+
+    /// Fetch the next record as a list, and return a [`TlvListIter`] that reads its items from the
+    /// inner reader on demand.
+    ///
+    /// The list must be fully iterated, or explicitly skipped via [`TlvListIter::skip`], before the
+    /// next record is fetched.
+    pub fn read_list_iter(&mut self) -> Result<TlvListIter<'_, R>, Error> {
+        let (ints, compact, strict, max_decompressed) =
+            (self.ints, self.compact, self.strict, self.max_decompressed);
+
+        let payload = self.next_reader()?.ok_or_else(|| {
+            Error::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Not enough bytes to read the list from the TLV format",
+            ))
+        })?;
+
+        // The items are read with the settings of this reader
+        let mut items = TlvReader::with_int_encoding(payload, ints);
+        items.compact = compact;
+        items.strict = strict;
+        items.max_decompressed = max_decompressed;
+
+        Ok(TlvListIter { items })
+    }
+
+    /// Read a list of serializable items from the provided reader
+    pub fn read_list<L: From<Vec<u8>>>(&mut self) -> Result<Vec<L>, Error> {
+        self.read_list_iter()?
+            .map(|item| item.map(L::from))
+            .collect()
     }
 
     /// Read a list of falible serializable items from the provided reader
     pub fn try_read_list<L: TryFrom<Vec<u8>, Error = Error>>(&mut self) -> Result<Vec<L>, Error> {
-        let buf = self.next().ok_or(Error::Io(io::Error::new(
-            io::ErrorKind::UnexpectedEof,
-            "Not enough bytes to read the list from the TLV format",
-        )))??;
+        self.read_list_iter()?
+            .map(|item| item.and_then(L::try_from))
+            .collect()
+    }
+}
+
+impl<'a> TlvReader<&'a [u8]> {
+    fn check_trailing(&self) -> Result<(), Error> {
+        if self.peeked.is_some() || !self.reader.reader.is_empty() {
+            return Err(Error::TrailingBytes(self.reader.reader.len()));
+        }
+
+        Ok(())
+    }
+}
 
-        let mut list = vec![];
-        for item in TlvReader::new(buf.as_slice()) {
-            let item = item?;
-            list.push(L::try_from(item)?);
+/// [`Read`] that optionally captures the bytes read from the inner reader
+struct Tap<R> {
+    reader: R,
+    capture: Option<Vec<u8>>,
+}
+
+impl<R> io::Read for Tap<R>
+where
+    R: io::Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let n = self.reader.read(buf)?;
+        if let Some(capture) = self.capture.as_mut() {
+            capture.extend_from_slice(&buf[..n]);
         }
 
-        Ok(list)
+        Ok(n)
+    }
+}
+
+/// [`Read`] that counts the bytes read from the inner reader
+struct Counter<'a, R> {
+    reader: &'a mut R,
+    n: u64,
+}
+
+impl<'a, R> io::Read for Counter<'a, R>
+where
+    R: io::Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let n = self.reader.read(buf)?;
+        self.n += n as u64;
+
+        Ok(n)
     }
 }
 
@@ -138,11 +629,19 @@ where
     ///
     /// Otherwise, the payload of the TLV will be returned
     fn next(&mut self) -> Option<Self::Item> {
-        let tlv_len = match TlvReader::reader_to_tlv_len(&mut self.reader) {
-            Ok(l) => l,
+        if let Err(e) = self.check_consumed() {
+            return Some(Err(e));
+        }
+
+        let (tag, tlv_len) = match self.next_header() {
+            Ok(h) => h,
             Err(_) => return None,
         };
 
+        if tag == COMPRESSED_TAG {
+            return Some(self.read_compressed(tlv_len, self.max_decompressed));
+        }
+
         let mut v = Vec::with_capacity(tlv_len);
 
         let reader = &mut self.reader;
@@ -163,17 +662,223 @@ where
     }
 }
 
+/// Lazy iterator over the payloads of the items of a list, provided by
+/// [`TlvReader::read_list_iter`].
+///
+/// The items are read from the inner reader on demand, bounded by the length of the list, so the
+/// list is never loaded in memory as a whole. Nested lists can be iterated the same way via
+/// [`TlvListIter::next_list`].
+pub struct TlvListIter<'a, R>
+where
+    R: io::Read,
+{
+    items: TlvReader<TlvPayload<'a, R>>,
+}
+
+impl<'a, R> TlvListIter<'a, R>
+where
+    R: io::Read,
+{
+    /// Amount of bytes of the list that were not yet consumed
+    pub fn remaining(&self) -> u64 {
+        self.items.reader.reader.remaining()
+    }
+
+    /// Fetch the next item as a nested list.
+    ///
+    /// Will return `Ok(None)` if there are no more items in the list.
+    pub fn next_list(&mut self) -> Result<Option<TlvListIter<'_, TlvPayload<'a, R>>>, Error> {
+        if self.remaining() == 0 {
+            return Ok(None);
+        }
+
+        self.items.read_list_iter().map(Some)
+    }
+
+    /// Discard the remaining items of the list, and return the amount of skipped bytes
+    pub fn skip(self) -> Result<u64, Error> {
+        self.items.into_inner().skip()
+    }
+}
+
+impl<'a, R> Iterator for TlvListIter<'a, R>
+where
+    R: io::Read,
+{
+    type Item = Result<Vec<u8>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining() == 0 {
+            return None;
+        }
+
+        Some(self.items.next().unwrap_or_else(|| {
+            Err(Error::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "The reader didnt provide enough bytes for the TLV decoding",
+            )))
+        }))
+    }
+}
+
+/// Length-limited [`Read`] over the payload of a single record, provided by
+/// [`TlvReader::next_reader`].
+///
+/// Every byte read from it is deducted from the payload length, and the reader will signal the
+/// end of the stream once the payload is exhausted.
+pub struct TlvPayload<'a, R>
+where
+    R: io::Read,
+{
+    tlv: &'a mut TlvReader<R>,
+    /// Decompressed payload of a compressed record, already consumed from the inner reader
+    inflated: Option<io::Cursor<Vec<u8>>>,
+}
+
+impl<'a, R> TlvPayload<'a, R>
+where
+    R: io::Read,
+{
+    /// Amount of bytes of the payload that were not yet consumed
+    pub fn remaining(&self) -> u64 {
+        match &self.inflated {
+            Some(c) => c.get_ref().len() as u64 - c.position(),
+            None => self.tlv.remaining,
+        }
+    }
+
+    /// Discard the remainder of the payload, and return the amount of skipped bytes
+    pub fn skip(mut self) -> Result<u64, Error> {
+        Ok(io::copy(&mut self, &mut io::sink())?)
+    }
+}
+
+impl<'a, R> io::Read for TlvPayload<'a, R>
+where
+    R: io::Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        if let Some(c) = self.inflated.as_mut() {
+            return c.read(buf);
+        }
+
+        let max = std::cmp::min(buf.len() as u64, self.tlv.remaining) as usize;
+        if max == 0 {
+            return Ok(0);
+        }
+
+        let n = self.tlv.reader.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "The reader didnt provide enough bytes for the TLV decoding",
+            ));
+        }
+
+        self.tlv.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
 impl<'de, R> SeqAccess<'de> for TlvReader<R>
 where
     R: io::Read,
 {
     type Error = Error;
 
-    fn next_element_seed<T>(&mut self, _seed: T) -> Result<Option<T::Value>, Self::Error>
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.is_exhausted() {
+            return Ok(None);
+        }
+
+        self.field = self.fields;
+        seed.deserialize(self).map(Some)
+    }
+}
+
+impl<'de, R> MapAccess<'de> for TlvReader<R>
+where
+    R: io::Read,
+{
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        if self.is_exhausted() {
+            return Ok(None);
+        }
+
+        self.read_key(|r| seed.deserialize(r)).map(Some)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        seed.deserialize(self)
+    }
+}
+
+/// The variant container starts with the 4 bytes variant index, followed by the variant fields
+impl<'de, 'a, R> EnumAccess<'de> for &'a mut TlvReader<R>
+where
+    R: io::Read,
+{
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let mut buf = [0x00u8; 4];
+        self.read_next_slice(&mut buf)?;
+
+        let index: U32Deserializer<Error> = u32::from_le_bytes(buf).into_deserializer();
+        Ok((seed.deserialize(index)?, self))
+    }
+}
+
+impl<'de, 'a, R> VariantAccess<'de> for &'a mut TlvReader<R>
+where
+    R: io::Read,
+{
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Self::Error>
     where
         T: DeserializeSeed<'de>,
     {
-        unimplemented!()
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.fields = self.compact;
+        visitor.visit_seq(self)
+    }
+
+    fn struct_variant<V>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.fields = self.compact;
+        visitor.visit_seq(self)
     }
 }
 
@@ -183,11 +888,12 @@ where
 {
     type Error = Error;
 
-    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    /// The format is not self-describing, so the payload of the next record is provided as bytes
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        visitor.visit_byte_buf(self.next_payload()?)
     }
 
     fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value, Self::Error>
@@ -195,7 +901,7 @@ where
         V: Visitor<'de>,
     {
         let mut buf = [0x00u8];
-        TlvReader::read_slice(&mut self.reader, &mut buf)?;
+        self.read_primitive(&mut buf, true)?;
         visitor.visit_bool(if buf[0] == 0 { false } else { true })
     }
 
@@ -203,72 +909,64 @@ where
     where
         V: Visitor<'de>,
     {
-        let mut buf = [0x00u8];
-        TlvReader::read_slice(&mut self.reader, &mut buf)?;
-        visitor.visit_i8(i8::from_le_bytes(buf))
+        let v = self.read_signed(std::mem::size_of::<i8>())?;
+        visitor.visit_i8(v as i8)
     }
 
     fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let mut buf = [0x00u8; 2];
-        TlvReader::read_slice(&mut self.reader, &mut buf)?;
-        visitor.visit_i16(i16::from_le_bytes(buf))
+        let v = self.read_signed(std::mem::size_of::<i16>())?;
+        visitor.visit_i16(v as i16)
     }
 
     fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let mut buf = [0x00u8; 4];
-        TlvReader::read_slice(&mut self.reader, &mut buf)?;
-        visitor.visit_i32(i32::from_le_bytes(buf))
+        let v = self.read_signed(std::mem::size_of::<i32>())?;
+        visitor.visit_i32(v as i32)
     }
 
     fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let mut buf = [0x00u8; 8];
-        TlvReader::read_slice(&mut self.reader, &mut buf)?;
-        visitor.visit_i64(i64::from_le_bytes(buf))
+        let v = self.read_signed(std::mem::size_of::<i64>())?;
+        visitor.visit_i64(v as i64)
     }
 
     fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let mut buf = [0x00u8; 1];
-        TlvReader::read_slice(&mut self.reader, &mut buf)?;
-        visitor.visit_u8(u8::from_le_bytes(buf))
+        let v = self.read_unsigned(std::mem::size_of::<u8>())?;
+        visitor.visit_u8(v as u8)
     }
 
     fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let mut buf = [0x00u8; 2];
-        TlvReader::read_slice(&mut self.reader, &mut buf)?;
-        visitor.visit_u16(u16::from_le_bytes(buf))
+        let v = self.read_unsigned(std::mem::size_of::<u16>())?;
+        visitor.visit_u16(v as u16)
     }
 
     fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let mut buf = [0x00u8; 4];
-        TlvReader::read_slice(&mut self.reader, &mut buf)?;
-        visitor.visit_u32(u32::from_le_bytes(buf))
+        let v = self.read_unsigned(std::mem::size_of::<u32>())?;
+        visitor.visit_u32(v as u32)
     }
 
     fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let mut buf = [0x00u8; 8];
-        TlvReader::read_slice(&mut self.reader, &mut buf)?;
-        visitor.visit_u64(u64::from_le_bytes(buf))
+        let v = self.read_unsigned(std::mem::size_of::<u64>())?;
+        visitor.visit_u64(v as u64)
     }
 
     fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value, Self::Error>
@@ -276,7 +974,7 @@ where
         V: Visitor<'de>,
     {
         let mut buf = [0x00u8; 4];
-        TlvReader::read_slice(&mut self.reader, &mut buf)?;
+        self.read_primitive(&mut buf, true)?;
         visitor.visit_f32(f32::from_le_bytes(buf))
     }
 
@@ -285,7 +983,7 @@ where
         V: Visitor<'de>,
     {
         let mut buf = [0x00u8; 8];
-        TlvReader::read_slice(&mut self.reader, &mut buf)?;
+        self.read_primitive(&mut buf, true)?;
         visitor.visit_f64(f64::from_le_bytes(buf))
     }
 
@@ -294,7 +992,7 @@ where
         V: Visitor<'de>,
     {
         let mut buf = [0x00u8; 1];
-        TlvReader::read_slice(&mut self.reader, &mut buf)?;
+        self.read_primitive(&mut buf, true)?;
         visitor.visit_char(char::from(buf[0]))
     }
 
@@ -302,78 +1000,52 @@ where
     where
         V: Visitor<'de>,
     {
-        unsafe {
-            self.next()
-                .unwrap_or(Err(Error::Io(io::Error::new(
-                    io::ErrorKind::UnexpectedEof,
-                    "It was not possible to deserialize the text",
-                ))))
-                .and_then(|bytes| {
-                    visitor.visit_str(std::str::from_utf8_unchecked(bytes.as_slice()))
-                })
-        }
+        self.deserialize_string(visitor)
     }
 
     fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        unsafe {
-            self.next()
-                .unwrap_or(Err(Error::Io(io::Error::new(
-                    io::ErrorKind::UnexpectedEof,
-                    "It was not possible to deserialize the text",
-                ))))
-                .and_then(|bytes| visitor.visit_string(String::from_utf8_unchecked(bytes)))
-        }
+        let text = String::from_utf8(self.next_payload()?)
+            .map_err(|e| Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+
+        visitor.visit_string(text)
     }
 
     fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        self.next()
-            .unwrap_or(Err(Error::Io(io::Error::new(
-                io::ErrorKind::UnexpectedEof,
-                "It was not possible to deserialize the text",
-            ))))
-            .and_then(|bytes| visitor.visit_bytes(bytes.as_slice()))
+        visitor.visit_bytes(self.next_payload()?.as_slice())
     }
 
     fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        self.next()
-            .unwrap_or(Err(Error::Io(io::Error::new(
-                io::ErrorKind::UnexpectedEof,
-                "It was not possible to deserialize the text",
-            ))))
-            .and_then(|bytes| visitor.visit_byte_buf(bytes))
+        visitor.visit_byte_buf(self.next_payload()?)
     }
 
+    /// [`None`] is an empty container, while [`Some`] is a container of the record of the value
     fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        self.next()
-            .unwrap_or(Err(Error::Io(io::Error::new(
-                io::ErrorKind::UnexpectedEof,
-                "It was not possible to deserialize the text",
-            ))))
-            .and_then(|bytes| {
-                if !bytes.is_empty() {
-                    visitor.visit_byte_buf(bytes)
-                } else {
-                    visitor.visit_none()
-                }
-            })
+        self.read_container(|c| {
+            if c.is_exhausted() {
+                return visitor.visit_none();
+            }
+
+            visitor.visit_some(c)
+        })
     }
 
     fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
+        self.read_next_slice(&mut [])?;
         visitor.visit_unit()
     }
 
@@ -385,17 +1057,22 @@ where
     where
         V: Visitor<'de>,
     {
-        visitor.visit_unit()
+        self.deserialize_unit(visitor)
     }
 
+    /// The elements of a set are read as keys
     fn deserialize_newtype_struct<V>(
         self,
-        _name: &'static str,
+        name: &'static str,
         visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
+        if name == SET_ELEMENT {
+            return self.read_key(|r| visitor.visit_newtype_struct(r));
+        }
+
         visitor.visit_newtype_struct(self)
     }
 
@@ -403,28 +1080,18 @@ where
     where
         V: Visitor<'de>,
     {
-        let buf = self.next().ok_or(Error::Io(io::Error::new(
-            io::ErrorKind::UnexpectedEof,
-            "Not enough bytes to read the list from the TLV format",
-        )))??;
-
-        let buf = TlvReader::new(buf.as_slice());
+        if self.peek_header()?.0 == PACKED_TAG {
+            return visit_packed(self.next_payload()?.as_slice(), visitor);
+        }
 
-        visitor.visit_seq(buf)
+        self.read_container(|c| visitor.visit_seq(c))
     }
 
     fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let buf = self.next().ok_or(Error::Io(io::Error::new(
-            io::ErrorKind::UnexpectedEof,
-            "Not enough bytes to read the list from the TLV format",
-        )))??;
-
-        let buf = TlvReader::new(buf.as_slice());
-
-        visitor.visit_seq(buf)
+        self.read_fields(|c| visitor.visit_seq(c))
     }
 
     fn deserialize_tuple_struct<V>(
@@ -436,21 +1103,14 @@ where
     where
         V: Visitor<'de>,
     {
-        let buf = self.next().ok_or(Error::Io(io::Error::new(
-            io::ErrorKind::UnexpectedEof,
-            "Not enough bytes to read the list from the TLV format",
-        )))??;
-
-        let buf = TlvReader::new(buf.as_slice());
-
-        visitor.visit_seq(buf)
+        self.read_fields(|c| visitor.visit_seq(c))
     }
 
-    fn deserialize_map<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        self.read_container(|c| visitor.visit_map(c))
     }
 
     fn deserialize_struct<V>(
@@ -462,40 +1122,41 @@ where
     where
         V: Visitor<'de>,
     {
-        let buf = self.next().ok_or(Error::Io(io::Error::new(
-            io::ErrorKind::UnexpectedEof,
-            "Not enough bytes to read the list from the TLV format",
-        )))??;
-
-        let buf = TlvReader::new(buf.as_slice());
-
-        visitor.visit_seq(buf)
+        self.read_fields(|c| visitor.visit_seq(c))
     }
 
     fn deserialize_enum<V>(
         self,
         _name: &'static str,
         _variants: &'static [&'static str],
-        _visitor: V,
+        visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        self.read_container(|c| visitor.visit_enum(c))
     }
 
-    fn deserialize_identifier<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    /// Identifiers are encoded as their index
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        self.deserialize_u32(visitor)
     }
 
-    fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        self.next_reader()?
+            .ok_or(Error::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Not enough bytes to read the payload from the TLV format",
+            )))?
+            .skip()?;
+
+        visitor.visit_unit()
     }
 }
 
@@ -504,9 +1165,45 @@ mod tests {
     use crate::*;
     use serde::de::Deserialize;
     use serde::ser::Serialize;
-    use std::io::{Cursor, Write};
+    use serde::{Deserialize as DeriveDeserialize, Serialize as DeriveSerialize};
+    use std::collections::BTreeMap;
+    use std::io::{self, Cursor, Read};
     use std::iter;
 
+    #[derive(Debug, PartialEq, DeriveSerialize, DeriveDeserialize)]
+    enum Shape {
+        Empty,
+        Circle(u32),
+        Rect { w: u16, h: u16 },
+    }
+
+    #[derive(Debug, PartialEq, DeriveSerialize, DeriveDeserialize)]
+    struct Drawing {
+        name: String,
+        shapes: Vec<Shape>,
+        layers: BTreeMap<u8, Option<i64>>,
+        scale: (f32, bool),
+    }
+
+    #[derive(Debug, PartialEq, DeriveSerialize, DeriveDeserialize)]
+    enum Tree {
+        Leaf(u16),
+        Node(Vec<Tree>),
+    }
+
+    #[derive(Debug, PartialEq, DeriveSerialize, DeriveDeserialize)]
+    struct Point {
+        x: u8,
+        y: u8,
+    }
+
+    #[derive(Debug, PartialEq, DeriveSerialize, DeriveDeserialize)]
+    struct Point3 {
+        x: u8,
+        y: u8,
+        z: u8,
+    }
+
     #[test]
     fn tlv_reader_vec() {
         let buf: Vec<u8> = iter::repeat(())
@@ -524,8 +1221,8 @@ mod tests {
         let cursor = Cursor::new(Vec::<u8>::new());
         let mut tlv_writer = TlvWriter::new(cursor);
 
-        tlv_writer.write(buf.as_slice()).unwrap();
-        tlv_writer.write(buf_other.as_slice()).unwrap();
+        tlv_writer.write_record(buf.as_slice()).unwrap();
+        tlv_writer.write_record(buf_other.as_slice()).unwrap();
 
         let mut cursor = tlv_writer.into_inner();
         cursor.set_position(0);
@@ -587,4 +1284,233 @@ mod tests {
 
         assert_eq!(input, output);
     }
+
+    #[test]
+    fn tlv_reader_typed() {
+        let mut tlv_writer = TlvWriter::new(vec![]);
+        assert_eq!(10, tlv_writer.write_usize(2558).unwrap());
+        tlv_writer.write_isize(-21).unwrap();
+        tlv_writer.encode(&37u64).unwrap();
+        tlv_writer.encode(&-5i16).unwrap();
+        tlv_writer.write_record(b"Foo").unwrap();
+        let v = tlv_writer.into_inner();
+
+        // The usize is written as an u64 regardless of the platform
+        assert_eq!(&[0xf1, 0x08, 0xfe, 0x09, 0x00, 0x00], &v[..6]);
+        assert_eq!(to_vec(&2558u64).unwrap(), &v[..10]);
+
+        let mut tlv_reader = TlvReader::new(v.as_slice());
+        assert_eq!(2558, tlv_reader.read_usize().unwrap());
+        assert_eq!(-21, tlv_reader.read_isize().unwrap());
+        assert_eq!(37, tlv_reader.read_u64().unwrap());
+        assert_eq!(-5, tlv_reader.read_i16().unwrap());
+        assert_eq!(b"Foo".to_vec(), tlv_reader.read_bytes().unwrap());
+        assert!(tlv_reader.read_bytes().is_err());
+
+        // Values wider than the portable type are rejected
+        let mut tlv_writer = TlvWriter::with_int_encoding(vec![], IntEncoding::Varint);
+        tlv_writer.encode(&(u64::MAX as u128 + 1)).unwrap();
+        let v = tlv_writer.into_inner();
+
+        let mut tlv_reader = TlvReader::with_int_encoding(v.as_slice(), IntEncoding::Varint);
+        assert!(tlv_reader.read_usize().is_err());
+    }
+
+    #[test]
+    fn tlv_reader_next_reader() {
+        let buf: Vec<u8> = iter::repeat(())
+            .take(65536)
+            .enumerate()
+            .map(|(i, _)| i as u8)
+            .collect();
+
+        let cursor = Cursor::new(Vec::<u8>::new());
+        let mut tlv_writer = TlvWriter::new(cursor);
+
+        tlv_writer.write_record(buf.as_slice()).unwrap();
+        tlv_writer.write_record(b"Foo").unwrap();
+        tlv_writer.write_record(b"Bar").unwrap();
+
+        let mut cursor = tlv_writer.into_inner();
+        cursor.set_position(0);
+
+        let mut tlv_reader = TlvReader::new(cursor);
+
+        let mut payload = tlv_reader.next_reader().unwrap().unwrap();
+        assert_eq!(65536, payload.remaining());
+        let mut fetch_vec = vec![];
+        io::copy(&mut payload, &mut fetch_vec).unwrap();
+        assert_eq!(buf, fetch_vec);
+
+        let mut payload = tlv_reader.next_reader().unwrap().unwrap();
+        let mut head = [0x00u8; 1];
+        payload.read_exact(&mut head).unwrap();
+        assert_eq!(b"F", &head);
+
+        // The payload was not fully consumed
+        assert!(tlv_reader.next().unwrap().is_err());
+        assert!(tlv_reader.next_reader().is_err());
+    }
+
+    #[test]
+    fn tlv_reader_next_reader_skip() {
+        let cursor = Cursor::new(Vec::<u8>::new());
+        let mut tlv_writer = TlvWriter::new(cursor);
+
+        tlv_writer.write_record(b"Hello World!").unwrap();
+        tlv_writer.write_record(b"Foo").unwrap();
+
+        let mut cursor = tlv_writer.into_inner();
+        cursor.set_position(0);
+
+        let mut tlv_reader = TlvReader::new(cursor);
+
+        let mut payload = tlv_reader.next_reader().unwrap().unwrap();
+        let mut head = [0x00u8; 5];
+        payload.read_exact(&mut head).unwrap();
+        assert_eq!(7, payload.skip().unwrap());
+
+        assert_eq!(b"Foo", tlv_reader.next().unwrap().unwrap().as_slice());
+        assert!(tlv_reader.next_reader().unwrap().is_none());
+
+        // The stream ends in the middle of the length bytes
+        let mut tlv_reader = TlvReader::new(&[0xf2, 0x05][..]);
+        assert!(tlv_reader.next_reader().is_err());
+    }
+
+    #[test]
+    fn tlv_reader_from_slice() {
+        let mut layers = BTreeMap::new();
+        layers.insert(1, Some(-5));
+        layers.insert(7, None);
+
+        let input = Drawing {
+            name: String::from("Foo"),
+            shapes: vec![
+                Shape::Empty,
+                Shape::Circle(25),
+                Shape::Rect { w: 3, h: 300 },
+            ],
+            layers,
+            scale: (1.5, true),
+        };
+
+        let v = to_vec(&input).unwrap();
+        assert_eq!(serialized_size(&input).unwrap(), v.len());
+
+        let output: Drawing = from_slice(v.as_slice()).unwrap();
+        assert_eq!(input, output);
+
+        let mut reader = v.as_slice();
+        let output: Drawing = from_reader(&mut reader).unwrap();
+        assert_eq!(input, output);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn tlv_reader_from_slice_trailing() {
+        let mut v = to_vec(&25u32).unwrap();
+        v.extend_from_slice(&[0xf0, 0xf0]);
+
+        match from_slice::<u32>(v.as_slice()) {
+            Err(Error::TrailingBytes(2)) => (),
+            r => panic!("Unexpected result {:?}", r),
+        }
+
+        // The reader is left pointing to the trailing bytes
+        let mut reader = v.as_slice();
+        assert_eq!(25u32, from_reader(&mut reader).unwrap());
+        assert_eq!(&[0xf0, 0xf0], reader);
+
+        // The nested container has one more field than the struct
+        let v = to_vec(&vec![Point3 { x: 1, y: 2, z: 3 }]).unwrap();
+        match from_slice::<Vec<Point>>(v.as_slice()) {
+            Err(Error::TrailingBytes(3)) => (),
+            r => panic!("Unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn tlv_reader_invalid_utf8() {
+        let v = to_vec(&vec![0xffu8, 0xfe]).unwrap();
+        assert!(from_slice::<String>(v.as_slice()).is_err());
+    }
+
+    #[test]
+    fn tlv_reader_list_iter() {
+        let mut writer = TlvWriter::new(vec![]);
+        {
+            let mut list = writer.begin_list();
+            list.write_item(b"Foo").unwrap();
+            {
+                let mut nested = list.begin_list();
+                nested.write_item(b"Bar").unwrap();
+                nested.begin_list().write_item(b"Deep").unwrap();
+            }
+            list.write_item(b"Qux").unwrap();
+        }
+        writer.write_list(vec![b"Skipped"]).unwrap();
+        writer.write_record(b"Last").unwrap();
+        let v = writer.into_inner();
+
+        let mut tlv_reader = TlvReader::new(Cursor::new(v.as_slice()));
+        {
+            let mut list = tlv_reader.read_list_iter().unwrap();
+            assert_eq!(b"Foo", list.next().unwrap().unwrap().as_slice());
+
+            {
+                let mut nested = list.next_list().unwrap().unwrap();
+                assert_eq!(b"Bar", nested.next().unwrap().unwrap().as_slice());
+
+                let mut deep = nested.next_list().unwrap().unwrap();
+                assert_eq!(b"Deep", deep.next().unwrap().unwrap().as_slice());
+                assert!(deep.next().is_none());
+                assert!(nested.next().is_none());
+            }
+
+            assert_eq!(b"Qux", list.next().unwrap().unwrap().as_slice());
+            assert!(list.next().is_none());
+            assert!(list.next_list().unwrap().is_none());
+        }
+
+        let list = tlv_reader.read_list_iter().unwrap();
+        assert_eq!(9, list.remaining());
+        assert_eq!(9, list.skip().unwrap());
+
+        assert_eq!(b"Last", tlv_reader.next().unwrap().unwrap().as_slice());
+    }
+
+    #[test]
+    fn tlv_reader_list_iter_truncated() {
+        let mut writer = TlvWriter::new(vec![]);
+        writer.write_list(vec![b"Foo", b"Bar"]).unwrap();
+        let v = writer.into_inner();
+
+        let mut tlv_reader = TlvReader::new(&v[..v.len() - 2]);
+        let mut list = tlv_reader.read_list_iter().unwrap();
+        assert_eq!(b"Foo", list.next().unwrap().unwrap().as_slice());
+        assert!(list.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn tlv_reader_nested_streaming() {
+        let tree = Tree::Node(vec![
+            Tree::Leaf(1),
+            Tree::Node(vec![Tree::Node(vec![Tree::Leaf(2)]), Tree::Leaf(3)]),
+            Tree::Node(vec![]),
+        ]);
+
+        let mut v = to_vec(&tree).unwrap();
+        v.extend(to_vec(&25u8).unwrap());
+
+        let mut cursor = Cursor::new(v.as_slice());
+        assert_eq!(tree, from_reader(&mut cursor).unwrap());
+        assert_eq!(25u8, from_reader(&mut cursor).unwrap());
+
+        // The inner list declares more bytes than its container
+        let mut v = to_vec(&(1u8, vec![2u8, 3])).unwrap();
+        v[1] -= 2;
+        v[6] += 2;
+        assert!(from_slice::<(u8, Vec<u8>)>(v.as_slice()).is_err());
+    }
 }
@@ -0,0 +1,205 @@
+//! Serde helpers to encode sets, such as `HashSet` or `BTreeSet`, canonically.
+//!
+//! Serde doesn't distinguish sets from sequences, so the elements of a set are marked for the
+//! [`crate::TlvWriter`] to sort them by their encoded bytes when it is canonical, and for the
+//! [`crate::TlvReader`] to reject unsorted or duplicated elements when it is strict. Other formats
+//! will see a regular sequence.
+//!
+//! ```
+//! use std::collections::HashSet;
+//!
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Peers {
+//!     #[serde(with = "dusk_tlv::set")]
+//!     addresses: HashSet<String>,
+//! }
+//! ```
+
+use std::fmt;
+use std::iter::FromIterator;
+use std::marker::PhantomData;
+
+use serde::de::{SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Name of the newtype struct that wraps every element of a set
+pub(crate) const SET_ELEMENT: &str = "$dusk_tlv::SetElement";
+
+/// Serialize the set as a sequence of marked elements
+pub fn serialize<S, T, E>(set: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    for<'a> &'a T: IntoIterator<Item = &'a E>,
+    E: Serialize,
+{
+    serializer.collect_seq(set.into_iter().map(SetElement))
+}
+
+/// Deserialize the set from a sequence of marked elements
+pub fn deserialize<'de, D, T, E>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: IntoIterator<Item = E> + FromIterator<E>,
+    E: Deserialize<'de>,
+{
+    deserializer.deserialize_seq(SetVisitor(PhantomData))
+}
+
+struct SetElement<E>(E);
+
+impl<E: Serialize> Serialize for SetElement<&E> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(SET_ELEMENT, self.0)
+    }
+}
+
+impl<'de, E: Deserialize<'de>> Deserialize<'de> for SetElement<E> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_newtype_struct(SET_ELEMENT, ElementVisitor(PhantomData))
+    }
+}
+
+struct ElementVisitor<E>(PhantomData<E>);
+
+impl<'de, E: Deserialize<'de>> Visitor<'de> for ElementVisitor<E> {
+    type Value = SetElement<E>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "an element of a set")
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        E::deserialize(deserializer).map(SetElement)
+    }
+}
+
+struct SetVisitor<T, E>(PhantomData<(T, E)>);
+
+impl<'de, T, E> Visitor<'de> for SetVisitor<T, E>
+where
+    T: FromIterator<E>,
+    E: Deserialize<'de>,
+{
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a set")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut elements = vec![];
+        while let Some(SetElement(e)) = seq.next_element()? {
+            elements.push(e);
+        }
+
+        Ok(elements.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    use std::collections::{BTreeMap, HashMap, HashSet};
+
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Registry {
+        names: HashMap<u16, String>,
+        #[serde(with = "crate::set")]
+        peers: HashSet<u64>,
+    }
+
+    fn registry(n: u16, capacity: usize) -> Registry {
+        let mut names = HashMap::with_capacity(capacity);
+        let mut peers = HashSet::with_capacity(capacity);
+
+        for i in 0..n {
+            let i = if capacity > 32 { i } else { n - 1 - i };
+            names.insert(i * 7, format!("Foo {}", i));
+            peers.insert(i as u64 * 300);
+        }
+
+        Registry { names, peers }
+    }
+
+    fn canonical<T: Serialize>(value: &T) -> Vec<u8> {
+        let mut writer = TlvWriter::new(vec![]);
+        writer.set_canonical(true);
+        value.serialize(&mut writer).unwrap();
+        writer.into_inner()
+    }
+
+    fn strict<'de, T: Deserialize<'de>>(v: &[u8]) -> Result<T, Error> {
+        let mut reader = TlvReader::new(v);
+        reader.set_strict(true);
+        T::deserialize(&mut reader)
+    }
+
+    #[test]
+    fn canonical_equal_values() {
+        let v = canonical(&registry(50, 0));
+        for capacity in [1, 64, 1024].iter() {
+            assert_eq!(v, canonical(&registry(50, *capacity)));
+        }
+
+        // The set is not packed, so its elements can be sorted as records
+        assert_eq!(v.len(), serialized_size(&registry(50, 0)).unwrap());
+        assert_eq!(registry(50, 3), strict(v.as_slice()).unwrap());
+
+        let names: BTreeMap<u16, String> = registry(50, 0).names.into_iter().collect();
+        let hashed = registry(50, 7).names;
+        assert_eq!(canonical(&names), canonical(&hashed));
+    }
+
+    #[test]
+    fn strict_rejects_non_canonical() {
+        // 256 is encoded as `0x00 0x01`, so it sorts before 1 in canonical order
+        let mut names = BTreeMap::new();
+        names.insert(1u16, String::from("Foo"));
+        names.insert(256u16, String::from("Bar"));
+
+        let v = to_vec(&names).unwrap();
+        assert_eq!(names, from_slice(v.as_slice()).unwrap());
+        assert!(strict::<BTreeMap<u16, String>>(v.as_slice()).is_err());
+
+        let v = canonical(&names);
+        assert_ne!(to_vec(&names).unwrap(), v);
+        assert_eq!(names, strict(v.as_slice()).unwrap());
+
+        // Duplicated keys are rejected only in maps, not in sequences
+        let mut writer = TlvWriter::new(vec![]);
+        writer.begin_container().unwrap();
+        writer.encode(&(1u16, "Foo")).unwrap();
+        writer.encode(&(1u16, "Bar")).unwrap();
+        writer.end_container().unwrap();
+        let v = writer.into_inner();
+        assert!(strict::<Vec<(u16, String)>>(v.as_slice()).is_ok());
+
+        let mut writer = TlvWriter::new(vec![]);
+        writer.begin_container().unwrap();
+        for (k, v) in [(1u16, "Foo"), (1u16, "Bar")].iter() {
+            writer.encode(k).unwrap();
+            writer.encode(*v).unwrap();
+        }
+        writer.end_container().unwrap();
+        let v = writer.into_inner();
+        assert!(from_slice::<HashMap<u16, String>>(v.as_slice()).is_ok());
+        assert!(strict::<HashMap<u16, String>>(v.as_slice()).is_err());
+    }
+}
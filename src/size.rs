@@ -0,0 +1,584 @@
+use crate::int::EncodedInt;
+use crate::packed::{Element, Packing};
+use crate::set::SET_ELEMENT;
+use crate::{Error, IntEncoding};
+
+use std::cell::RefCell;
+
+use serde::ser::{
+    SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple,
+    SerializeTupleStruct, SerializeTupleVariant,
+};
+use serde::Serialize;
+
+/// Encoded length of the index of an enum variant, written as the first record of 4 bytes of the
+/// variant container
+const VARIANT_LEN: usize = 6;
+
+macro_rules! size_ser_trait {
+    ($t:ty,$m:ident) => {
+        impl<'a> $t for SizeCompound<'a> {
+            type Ok = usize;
+            type Error = Error;
+
+            fn $m<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+            where
+                T: Serialize,
+            {
+                self.len += value.serialize(self.field())?;
+                Ok(())
+            }
+
+            fn end(self) -> Result<Self::Ok, Self::Error> {
+                Ok(self.close())
+            }
+        }
+    };
+}
+
+macro_rules! size_ser_trait_two {
+    ($t:ty,$m:ident,$m2:ident) => {
+        impl<'a> $t for SizeCompound<'a> {
+            type Ok = usize;
+            type Error = Error;
+
+            fn $m<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+            where
+                T: Serialize,
+            {
+                self.len += value.serialize(self.element())?;
+                Ok(())
+            }
+
+            fn $m2<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+            where
+                T: Serialize,
+            {
+                self.len += value.serialize(self.element())?;
+                Ok(())
+            }
+
+            fn end(self) -> Result<Self::Ok, Self::Error> {
+                Ok(self.close())
+            }
+        }
+    };
+}
+
+macro_rules! size_ser_trait_field {
+    ($t:ty,$m:ident) => {
+        impl<'a> $t for SizeCompound<'a> {
+            type Ok = usize;
+            type Error = Error;
+
+            fn $m<T: ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<(), Self::Error>
+            where
+                T: Serialize,
+            {
+                self.len += value.serialize(self.field())?;
+                Ok(())
+            }
+
+            fn end(self) -> Result<Self::Ok, Self::Error> {
+                Ok(self.close())
+            }
+        }
+    };
+}
+
+/// Amount of bytes of the type and length of a payload of the provided length
+pub fn header_len(payload_len: usize) -> usize {
+    if payload_len == 0 {
+        return 1;
+    }
+
+    // The length mask doubles whenever the length needs more bytes, and the widest length is
+    // composed by 8 bytes
+    let mut len_mask = 0x01;
+    let mut n = payload_len >> 8;
+    while n > 0 && len_mask < 8 {
+        len_mask <<= 1;
+        n >>= 8;
+    }
+
+    len_mask + 1
+}
+
+/// Amount of bytes of a record with a payload of the provided length, header included
+pub fn encoded_len(payload_len: usize) -> usize {
+    header_len(payload_len) + payload_len
+}
+
+/// Return the exact amount of bytes the provided value will occupy when serialized by a
+/// [`crate::TlvWriter`], without writing any bytes.
+pub fn serialized_size<T: ?Sized>(value: &T) -> Result<usize, Error>
+where
+    T: Serialize,
+{
+    serialized_size_with(value, IntEncoding::default())
+}
+
+/// Return the exact amount of bytes the provided value will occupy when serialized by a
+/// [`crate::TlvWriter`] with the provided encoding of integers.
+pub fn serialized_size_with<T: ?Sized>(value: &T, ints: IntEncoding) -> Result<usize, Error>
+where
+    T: Serialize,
+{
+    size_with(value, ints, false)
+}
+
+/// Encoded length of the value with the provided settings of a [`crate::TlvWriter`]
+pub(crate) fn size_with<T: ?Sized>(
+    value: &T,
+    ints: IntEncoding,
+    compact: bool,
+) -> Result<usize, Error>
+where
+    T: Serialize,
+{
+    value.serialize(SizeSerializer {
+        ints,
+        compact,
+        field: false,
+        layouts: None,
+        slot: None,
+    })
+}
+
+/// Layouts of the containers of the value with the provided settings of a [`crate::TlvWriter`],
+/// in the order they are opened
+pub(crate) fn layouts_with<T: ?Sized>(
+    value: &T,
+    ints: IntEncoding,
+    compact: bool,
+) -> Result<Vec<Layout>, Error>
+where
+    T: Serialize,
+{
+    let layouts = RefCell::new(vec![]);
+    value.serialize(SizeSerializer {
+        ints,
+        compact,
+        field: false,
+        layouts: Some(&layouts),
+        slot: None,
+    })?;
+
+    Ok(layouts.into_inner())
+}
+
+/// Layout of a serde container, known before its records are written
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Layout {
+    /// Length of the payload
+    pub len: usize,
+    /// The payload is a packed sequence
+    pub packed: bool,
+    /// The records are keys that a canonical writer sorts, so the payload must be buffered
+    pub keyed: bool,
+}
+
+/// [`serde::Serializer`] that returns the encoded length of the value, instead of writing it
+#[derive(Clone, Copy)]
+struct SizeSerializer<'a> {
+    ints: IntEncoding,
+    compact: bool,
+    field: bool,
+    layouts: Option<&'a RefCell<Vec<Layout>>>,
+    slot: Option<usize>,
+}
+
+impl<'a> SizeSerializer<'a> {
+    /// Encoded length of a primitive, that has no header if it is fixed-size and a field of a
+    /// compact struct
+    fn primitive(self, len: usize, fixed: bool) -> usize {
+        if fixed && self.field {
+            len
+        } else {
+            encoded_len(len)
+        }
+    }
+
+    /// Encoded length of an integer, that is fixed-size unless encoded as varint
+    fn int(self, n: EncodedInt) -> usize {
+        self.primitive(n.as_slice().len(), self.ints != IntEncoding::Varint)
+    }
+
+    /// Open the layout of a container, if the layouts are collected, and return the serializer of
+    /// its records
+    fn open(self) -> Self {
+        let slot = self.layouts.map(|l| {
+            let mut l = l.borrow_mut();
+            l.push(Layout::default());
+            l.len() - 1
+        });
+
+        SizeSerializer {
+            field: false,
+            slot,
+            ..self
+        }
+    }
+
+    /// Record the payload length of the container of this serializer, and return its encoded
+    /// length
+    fn close(self, len: usize, packed: bool) -> usize {
+        self.update(|l| {
+            l.len = len;
+            l.packed = packed;
+        });
+
+        encoded_len(len)
+    }
+
+    /// Update the layout of the container of this serializer, if the layouts are collected
+    fn update<F: FnOnce(&mut Layout)>(self, f: F) {
+        if let (Some(layouts), Some(slot)) = (self.layouts, self.slot) {
+            f(&mut layouts.borrow_mut()[slot]);
+        }
+    }
+}
+
+/// Accumulate the payload length of a container
+struct SizeCompound<'a> {
+    ser: SizeSerializer<'a>,
+    len: usize,
+    packing: Packing,
+    /// Length of the packed elements if they were unpacked into records
+    unpacked_len: usize,
+}
+
+impl<'a> SizeCompound<'a> {
+    fn new(ser: SizeSerializer<'a>, len: usize) -> Self {
+        SizeCompound {
+            ser: ser.open(),
+            len,
+            packing: Packing::Records,
+            unpacked_len: 0,
+        }
+    }
+
+    /// Serializer of an element of a sequence or map
+    fn element(&self) -> SizeSerializer<'a> {
+        self.ser
+    }
+
+    /// Serializer of a field of a struct or tuple
+    fn field(&self) -> SizeSerializer<'a> {
+        SizeSerializer {
+            field: self.ser.compact,
+            ..self.ser
+        }
+    }
+
+    /// Encoded length of the container
+    fn close(self) -> usize {
+        self.ser.close(self.len, self.packing.is_packed())
+    }
+}
+
+/// Numeric elements of a sequence are packed, so they occupy only their own bytes
+impl<'a> SerializeSeq for SizeCompound<'a> {
+    type Ok = usize;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize,
+    {
+        self.len += match self.packing.pack(value) {
+            Element::Packed(p) => {
+                self.unpacked_len += p.serialize(self.element())?;
+                p.as_slice().len()
+            }
+            Element::Record => value.serialize(self.element())?,
+            Element::Unpack(..) => {
+                self.len = self.unpacked_len;
+                value.serialize(self.element())?
+            }
+        };
+
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self.close())
+    }
+}
+
+size_ser_trait!(SerializeTuple, serialize_element);
+size_ser_trait!(SerializeTupleStruct, serialize_field);
+size_ser_trait!(SerializeTupleVariant, serialize_field);
+size_ser_trait_two!(SerializeMap, serialize_key, serialize_value);
+size_ser_trait_field!(SerializeStruct, serialize_field);
+size_ser_trait_field!(SerializeStructVariant, serialize_field);
+
+impl<'a> serde::Serializer for SizeSerializer<'a> {
+    type Ok = usize;
+    type Error = Error;
+    type SerializeSeq = SizeCompound<'a>;
+    type SerializeTuple = SizeCompound<'a>;
+    type SerializeTupleStruct = SizeCompound<'a>;
+    type SerializeTupleVariant = SizeCompound<'a>;
+    type SerializeMap = SizeCompound<'a>;
+    type SerializeStruct = SizeCompound<'a>;
+    type SerializeStructVariant = SizeCompound<'a>;
+
+    fn serialize_bool(self, _v: bool) -> Result<Self::Ok, Self::Error> {
+        Ok(self.primitive(1, true))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
+        Ok(self.int(
+            self.ints
+                .encode_signed(v as i128, std::mem::size_of::<i8>()),
+        ))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
+        Ok(self.int(
+            self.ints
+                .encode_signed(v as i128, std::mem::size_of::<i16>()),
+        ))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
+        Ok(self.int(
+            self.ints
+                .encode_signed(v as i128, std::mem::size_of::<i32>()),
+        ))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
+        Ok(self.int(
+            self.ints
+                .encode_signed(v as i128, std::mem::size_of::<i64>()),
+        ))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
+        Ok(self.int(
+            self.ints
+                .encode_unsigned(v as u128, std::mem::size_of::<u8>()),
+        ))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
+        Ok(self.int(
+            self.ints
+                .encode_unsigned(v as u128, std::mem::size_of::<u16>()),
+        ))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
+        Ok(self.int(
+            self.ints
+                .encode_unsigned(v as u128, std::mem::size_of::<u32>()),
+        ))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
+        Ok(self.int(
+            self.ints
+                .encode_unsigned(v as u128, std::mem::size_of::<u64>()),
+        ))
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<Self::Ok, Self::Error> {
+        Ok(self.primitive(4, true))
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Self::Ok, Self::Error> {
+        Ok(self.primitive(8, true))
+    }
+
+    fn serialize_char(self, _v: char) -> Result<Self::Ok, Self::Error> {
+        Ok(self.primitive(1, true))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
+        Ok(encoded_len(v.len()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
+        Ok(encoded_len(v.len()))
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self.open().close(0, false))
+    }
+
+    fn serialize_some<T: ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error>
+    where
+        T: Serialize,
+    {
+        let ser = self.open();
+        let len = value.serialize(ser)?;
+
+        Ok(ser.close(len, false))
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+        Ok(encoded_len(0))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
+        Ok(encoded_len(0))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<Self::Ok, Self::Error> {
+        Ok(self.open().close(VARIANT_LEN, false))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error>
+    where
+        T: Serialize,
+    {
+        if name == SET_ELEMENT {
+            self.update(|l| l.keyed = true);
+        }
+
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error>
+    where
+        T: Serialize,
+    {
+        let ser = self.open();
+        let len = VARIANT_LEN + value.serialize(ser)?;
+
+        Ok(ser.close(len, false))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        Ok(SizeCompound {
+            packing: Packing::Undecided,
+            ..SizeCompound::new(self, 0)
+        })
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        Ok(SizeCompound::new(self, 0))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        Ok(SizeCompound::new(self, 0))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        Ok(SizeCompound::new(self, VARIANT_LEN))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        let map = SizeCompound::new(self, 0);
+        map.ser.update(|l| l.keyed = true);
+
+        Ok(map)
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        Ok(SizeCompound::new(self, 0))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        Ok(SizeCompound::new(self, VARIANT_LEN))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use serde::Serialize;
+
+    fn written_len<T: Serialize>(value: &T) -> usize {
+        let mut writer = TlvWriter::new(vec![]);
+        value.serialize(&mut writer).unwrap();
+        writer.into_inner().len()
+    }
+
+    #[test]
+    fn encoded_len_header() {
+        assert_eq!(1, encoded_len(0));
+        assert_eq!(2 + 255, encoded_len(255));
+        assert_eq!(3 + 256, encoded_len(256));
+        assert_eq!(5 + 65536, encoded_len(65536));
+        assert_eq!(9 + (1 << 32), encoded_len(1 << 32));
+    }
+
+    #[test]
+    fn serialized_size_matches_writer() {
+        let text = "a".repeat(300);
+
+        let value = (
+            25u64,
+            true,
+            Some(text),
+            vec![vec![1u16, 2, 3], vec![], vec![4]],
+            (-5i8, 2.5f64, None::<u32>),
+        );
+
+        let size = serialized_size(&value).unwrap();
+        assert_eq!(written_len(&value), size);
+        assert_eq!(10, serialized_size(&25u64).unwrap());
+    }
+
+    #[derive(Serialize)]
+    enum Shape {
+        Empty,
+        Circle(u32),
+        Segment(i16, i16),
+        Rect { w: u64, h: u64 },
+    }
+
+    #[test]
+    fn serialized_size_enums() {
+        let shapes = [
+            Shape::Empty,
+            Shape::Circle(5),
+            Shape::Segment(-1, 1),
+            Shape::Rect { w: 3, h: 4 },
+        ];
+
+        for shape in &shapes {
+            assert_eq!(written_len(shape), serialized_size(shape).unwrap());
+        }
+
+        assert_eq!(written_len(&shapes), serialized_size(&shapes).unwrap());
+    }
+}
@@ -0,0 +1,603 @@
+use crate::compress::{decompress, COMPRESSED_TAG, DEFAULT_MAX_DECOMPRESSED_LEN};
+use crate::packed::PACKED_TAG;
+use crate::writer::encode_header;
+use crate::{encoded_len, header_len, Error, TlvWriter};
+
+use std::io::{self, Read};
+
+mod de;
+mod ser;
+
+pub use de::from_value;
+pub use ser::to_value;
+
+/// Upper nibble of the default TLV type
+const DEFAULT_TAG: u8 = 0x0f;
+
+/// In-memory representation of a TLV record.
+///
+/// The payload of a record is a plain slice of bytes, so whether it is composed by nested records
+/// is defined when the value is parsed, either by a depth-aware heuristic via [`TlvValue::parse`],
+/// or by a [`TlvSchema`] via [`TlvValue::parse_with`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TlvValue {
+    /// Payload that is not decoded any further
+    Bytes(Vec<u8>),
+    /// Payload composed by a sequence of records
+    List(Vec<TlvValue>),
+    /// Record with a type other than `0xf`x``, where the tag is the upper nibble of the type.
+    ///
+    /// Should be built via [`TlvValue::tagged`], since tags that do not fit a record type are
+    /// rejected when the value is encoded.
+    Tagged(u8, Box<TlvValue>),
+}
+
+/// Hints of the structure of a TLV record, to parse it unambiguously into a [`TlvValue`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TlvSchema {
+    /// The payload is a plain slice of bytes
+    Bytes,
+    /// The payload is a list of records that follow the same schema
+    List(Box<TlvSchema>),
+    /// The payload is a list of records that follow the provided schemas, in order
+    Tuple(Vec<TlvSchema>),
+    /// The structure of the payload is guessed, up to the provided depth
+    Any(usize),
+}
+
+impl TlvValue {
+    /// Tag the provided value with the upper nibble of the record type.
+    ///
+    /// The default tag `0xf` leaves the value untagged. Tags wider than a nibble, and values that
+    /// are already tagged, are rejected since a record has a single type. The tags of compressed
+    /// records, `0xd`, and packed sequences, `0xe`, are reserved.
+    pub fn tagged(tag: u8, value: TlvValue) -> Result<Self, Error> {
+        if tag == DEFAULT_TAG {
+            return Ok(value);
+        }
+
+        if tag == PACKED_TAG {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The tag of packed sequences is reserved",
+            )));
+        }
+
+        check_tag(tag, &value)?;
+        Ok(TlvValue::Tagged(tag, Box::new(value)))
+    }
+
+    /// Parse a single record from the provided reader.
+    ///
+    /// A payload will be parsed as a [`TlvValue::List`] if it is not empty, the maximum depth is
+    /// not reached, and it is exactly composed by well-formed records.
+    /// Otherwise, it will be parsed as [`TlvValue::Bytes`].
+    pub fn parse<R: io::Read>(reader: R, max_depth: usize) -> Result<Self, Error> {
+        TlvValue::parse_with(reader, &TlvSchema::Any(max_depth))
+    }
+
+    /// Parse a single record from the provided reader, following the provided schema.
+    ///
+    /// Compressed records are decompressed, so the value holds their original payload. The
+    /// decompressed payloads of all the records of the value amount to at most
+    /// [`DEFAULT_MAX_DECOMPRESSED_LEN`].
+    pub fn parse_with<R: io::Read>(reader: R, schema: &TlvSchema) -> Result<Self, Error> {
+        let mut budget = DEFAULT_MAX_DECOMPRESSED_LEN;
+        TlvValue::parse_budget(reader, schema, &mut budget)
+    }
+
+    /// Parse a single record from the provided reader, following the provided schema, with the
+    /// provided maximum length of all the decompressed payloads of the value.
+    #[cfg(feature = "compression")]
+    pub fn parse_with_max_decompressed_len<R: io::Read>(
+        reader: R,
+        schema: &TlvSchema,
+        max_len: usize,
+    ) -> Result<Self, Error> {
+        let mut budget = max_len;
+        TlvValue::parse_budget(reader, schema, &mut budget)
+    }
+
+    /// Parse a single record, deducting its decompressed payloads from the remaining budget
+    fn parse_budget<R: io::Read>(
+        reader: R,
+        schema: &TlvSchema,
+        budget: &mut usize,
+    ) -> Result<Self, Error> {
+        let mut reader = reader;
+
+        let (tag, len) = read_header(&mut reader)?.ok_or_else(|| {
+            Error::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Not enough bytes to read the TLV value",
+            ))
+        })?;
+
+        // A record is compressed only if it gets smaller
+        if tag == COMPRESSED_TAG && len > *budget {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The compressed payload exceeds the maximum length",
+            )));
+        }
+
+        // The length is not trusted, so the payload grows only as the reader provides bytes
+        let mut payload = vec![];
+        reader.take(len as u64).read_to_end(&mut payload)?;
+        if payload.len() < len {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "The reader didnt provide enough bytes for the TLV decoding",
+            )));
+        }
+
+        if tag == COMPRESSED_TAG {
+            let payload = decompress(payload.as_slice(), *budget)?;
+            *budget -= payload.len();
+            return TlvValue::from_payload(payload, schema, budget);
+        }
+
+        // The payload of a packed sequence is never composed by records
+        if tag == PACKED_TAG {
+            return Ok(TlvValue::Tagged(tag, Box::new(TlvValue::Bytes(payload))));
+        }
+
+        let value = TlvValue::from_payload(payload, schema, budget)?;
+        if tag == DEFAULT_TAG {
+            Ok(value)
+        } else {
+            Ok(TlvValue::Tagged(tag, Box::new(value)))
+        }
+    }
+
+    /// Parse all the records of the provided slice, up to the provided depth
+    pub fn parse_slice(buf: &[u8], max_depth: usize) -> Result<Vec<Self>, Error> {
+        let mut buf = buf;
+        let mut values = vec![];
+
+        while !buf.is_empty() {
+            values.push(TlvValue::parse(&mut buf, max_depth)?);
+        }
+
+        Ok(values)
+    }
+
+    fn from_payload(
+        payload: Vec<u8>,
+        schema: &TlvSchema,
+        budget: &mut usize,
+    ) -> Result<Self, Error> {
+        match schema {
+            TlvSchema::Bytes => Ok(TlvValue::Bytes(payload)),
+
+            TlvSchema::List(item) => {
+                let mut buf = payload.as_slice();
+                let mut list = vec![];
+                while !buf.is_empty() {
+                    list.push(TlvValue::parse_budget(&mut buf, item, budget)?);
+                }
+
+                Ok(TlvValue::List(list))
+            }
+
+            TlvSchema::Tuple(items) => {
+                let mut buf = payload.as_slice();
+                let list = items
+                    .iter()
+                    .map(|item| TlvValue::parse_budget(&mut buf, item, budget))
+                    .collect::<Result<Vec<TlvValue>, Error>>()?;
+
+                if !buf.is_empty() {
+                    return Err(Error::Io(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "The payload contains more records than the schema",
+                    )));
+                }
+
+                Ok(TlvValue::List(list))
+            }
+
+            TlvSchema::Any(depth) if *depth > 0 && is_list(payload.as_slice()) => {
+                let item = TlvSchema::Any(depth - 1);
+                let remaining = *budget;
+
+                let mut buf = payload.as_slice();
+                let mut list = vec![];
+                while !buf.is_empty() {
+                    match TlvValue::parse_budget(&mut buf, &item, budget) {
+                        Ok(v) => list.push(v),
+
+                        // The payload only looks like a list, such as plain bytes that begin
+                        // with the type of a compressed record
+                        Err(_) => {
+                            *budget = remaining;
+                            return Ok(TlvValue::Bytes(payload));
+                        }
+                    }
+                }
+
+                Ok(TlvValue::List(list))
+            }
+
+            TlvSchema::Any(_) => Ok(TlvValue::Bytes(payload)),
+        }
+    }
+
+    /// Return the payload, if the value is [`TlvValue::Bytes`]
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            TlvValue::Bytes(b) => Some(b.as_slice()),
+            _ => None,
+        }
+    }
+
+    /// Return the nested values, if the value is [`TlvValue::List`]
+    pub fn as_list(&self) -> Option<&[TlvValue]> {
+        match self {
+            TlvValue::List(l) => Some(l.as_slice()),
+            _ => None,
+        }
+    }
+
+    /// Amount of bytes of the payload of the value
+    pub fn payload_len(&self) -> usize {
+        match self {
+            TlvValue::Bytes(b) => b.len(),
+            TlvValue::List(l) => l.iter().map(|v| v.encoded_len()).sum(),
+            TlvValue::Tagged(_, v) => v.payload_len(),
+        }
+    }
+
+    /// Amount of bytes of the encoded value, header included
+    pub fn encoded_len(&self) -> usize {
+        encoded_len(self.payload_len())
+    }
+
+    /// Encode the value in TLV format to the provided writer, and return the amount of bytes
+    /// written.
+    ///
+    /// Since the length of every nested list can be computed in advance, the value is streamed to
+    /// the writer without intermediate buffers.
+    pub fn encode<W: io::Write>(&self, writer: W) -> Result<usize, Error> {
+        let mut writer = writer;
+        self.encode_tagged(&mut writer, DEFAULT_TAG)
+    }
+
+    fn encode_tagged(&self, writer: &mut dyn io::Write, tag: u8) -> Result<usize, Error> {
+        let len = match self {
+            TlvValue::Tagged(t, v) => {
+                check_tag(*t, v)?;
+                return v.encode_tagged(writer, *t);
+            }
+            _ => self.payload_len(),
+        };
+
+        let (mut header, header_len) = encode_header(len);
+        header[0] = (tag << 4) | (header[0] & 0x0f);
+        writer.write_all(&header[..header_len])?;
+
+        if let TlvValue::Bytes(b) = self {
+            writer.write_all(b.as_slice())?;
+        } else if let TlvValue::List(l) = self {
+            for v in l {
+                v.encode_tagged(writer, DEFAULT_TAG)?;
+            }
+        }
+
+        Ok(header_len + len)
+    }
+
+    /// Encode the value into a new [`Vec`]
+    pub fn to_vec(&self) -> Result<Vec<u8>, Error> {
+        let mut v = Vec::with_capacity(self.encoded_len());
+        self.encode(&mut v)?;
+
+        Ok(v)
+    }
+}
+
+impl<W> TlvWriter<W>
+where
+    W: io::Write,
+{
+    /// Append the provided [`TlvValue`] to the writer
+    pub fn write_value(&mut self, value: &TlvValue) -> Result<usize, Error> {
+        value.encode(self.sink())
+    }
+}
+
+/// Check if the provided tag and value can be encoded as a single tagged record. The payload of a
+/// packed sequence, as produced by [`crate::to_value`], is plain bytes.
+fn check_tag(tag: u8, value: &TlvValue) -> Result<(), Error> {
+    if tag >= DEFAULT_TAG {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The tag of a TLV value must be lower than 0xf",
+        )));
+    }
+
+    if tag == COMPRESSED_TAG {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The tag of compressed records is reserved",
+        )));
+    }
+
+    if tag == PACKED_TAG && value.as_bytes().is_none() {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The payload of a packed sequence must be plain bytes",
+        )));
+    }
+
+    if let TlvValue::Tagged(..) = value {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "A tagged TLV value cannot be tagged again",
+        )));
+    }
+
+    Ok(())
+}
+
+/// Read the type and length of the next record. Will return `None` if the reader is exhausted.
+fn read_header<R: io::Read>(reader: &mut R) -> Result<Option<(u8, usize)>, Error> {
+    let mut tlv_type = [0x00u8];
+    if reader.read(&mut tlv_type)? == 0 {
+        return Ok(None);
+    }
+
+    let len = (tlv_type[0] & 0x0f) as usize;
+    if len > 8 {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The TLV type defines a length wider than 8 bytes",
+        )));
+    }
+
+    let mut tlv_len = [0x00u8; 8];
+    reader.read_exact(&mut tlv_len[..len])?;
+
+    Ok(Some((
+        tlv_type[0] >> 4,
+        u64::from_le_bytes(tlv_len) as usize,
+    )))
+}
+
+/// Check if the provided payload is exactly composed by well-formed records, with headers in the
+/// canonical form produced by [`TlvWriter`]
+fn is_list(payload: &[u8]) -> bool {
+    let mut pos = 0;
+
+    while pos < payload.len() {
+        let len = (payload[pos] & 0x0f) as usize;
+        if payload.len() < pos + 1 + len {
+            return false;
+        }
+
+        let mut tlv_len = [0x00u8; 8];
+        tlv_len[..len.min(8)].copy_from_slice(&payload[pos + 1..pos + 1 + len.min(8)]);
+        let tlv_len = u64::from_le_bytes(tlv_len);
+
+        if tlv_len > usize::MAX as u64 || header_len(tlv_len as usize) != len + 1 {
+            return false;
+        }
+
+        match ((pos + 1 + len) as u64).checked_add(tlv_len) {
+            Some(end) if end <= payload.len() as u64 => pos = end as usize,
+            _ => return false,
+        }
+    }
+
+    !payload.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum Kind {
+        Empty,
+        Amount(u64),
+        Pair(u8, i16),
+        Named { id: u32, tag: Option<String> },
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Message {
+        id: u64,
+        text: String,
+        kinds: Vec<Kind>,
+        nested: Vec<Vec<u16>>,
+        extra: Option<bool>,
+    }
+
+    fn message() -> Message {
+        Message {
+            id: 2533,
+            text: "Hello World!".into(),
+            kinds: vec![
+                Kind::Empty,
+                Kind::Amount(25),
+                Kind::Pair(3, -7),
+                Kind::Named {
+                    id: 12,
+                    tag: Some("Foo".into()),
+                },
+            ],
+            nested: vec![vec![1, 2], vec![], vec![3]],
+            extra: None,
+        }
+    }
+
+    fn sample() -> TlvValue {
+        TlvValue::List(vec![
+            TlvValue::Bytes(b"Foo".to_vec()),
+            TlvValue::List(vec![
+                TlvValue::Bytes(b"Bar".to_vec()),
+                TlvValue::Bytes(vec![]),
+            ]),
+            TlvValue::tagged(0x03, TlvValue::Bytes(b"Baz".to_vec())).unwrap(),
+        ])
+    }
+
+    #[test]
+    fn value_encode_matches_writer() {
+        let value = TlvValue::List(vec![
+            TlvValue::Bytes(b"Foo".to_vec()),
+            TlvValue::List(vec![TlvValue::Bytes(b"Bar".to_vec())]),
+        ]);
+
+        let mut writer = TlvWriter::new(vec![]);
+        let mut list = writer.begin_list();
+        list.write_item(b"Foo").unwrap();
+        list.begin_list().write_item(b"Bar").unwrap();
+        list.finish().unwrap();
+
+        let v = writer.into_inner();
+        assert_eq!(v, value.to_vec().unwrap());
+        assert_eq!(v.len(), value.encoded_len());
+    }
+
+    #[test]
+    fn value_parse_heuristic() {
+        let value = sample();
+        let v = value.to_vec().unwrap();
+
+        assert_eq!(value, TlvValue::parse(v.as_slice(), 8).unwrap());
+
+        // The nested list is not expanded when the depth is exhausted
+        let shallow = TlvValue::parse(v.as_slice(), 1).unwrap();
+        let list = shallow.as_list().unwrap();
+        assert_eq!(Some(&b"Foo"[..]), list[0].as_bytes());
+        assert!(list[1].as_bytes().is_some());
+
+        let values = TlvValue::parse_slice(&[v.clone(), v].concat(), 8).unwrap();
+        assert_eq!(vec![value.clone(), value], values);
+    }
+
+    #[test]
+    fn value_parse_schema() {
+        // A payload of 2 bytes that looks like an empty record followed by another one
+        let mut writer = TlvWriter::new(vec![]);
+        writer.write_record(&[0xf0, 0xf0]).unwrap();
+        let v = writer.into_inner();
+
+        let guessed = TlvValue::parse(v.as_slice(), 8).unwrap();
+        assert_eq!(2, guessed.as_list().unwrap().len());
+
+        let parsed = TlvValue::parse_with(v.as_slice(), &TlvSchema::Bytes).unwrap();
+        assert_eq!(TlvValue::Bytes(vec![0xf0, 0xf0]), parsed);
+
+        let schema = TlvSchema::Tuple(vec![TlvSchema::Bytes]);
+        assert!(TlvValue::parse_with(v.as_slice(), &schema).is_err());
+
+        let schema = TlvSchema::List(Box::new(TlvSchema::Bytes));
+        let parsed = TlvValue::parse_with(v.as_slice(), &schema).unwrap();
+        assert_eq!(
+            TlvValue::List(vec![TlvValue::Bytes(vec![]), TlvValue::Bytes(vec![])]),
+            parsed
+        );
+    }
+
+    #[test]
+    fn value_parse_untrusted_len() {
+        // A header that claims a payload of almost 2^60 bytes
+        let v = [0xf8, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x0f];
+        assert!(TlvValue::parse(&v[..], 8).is_err());
+        assert!(TlvValue::parse_with(&v[..], &TlvSchema::Bytes).is_err());
+    }
+
+    #[test]
+    fn value_tags() {
+        let bytes = TlvValue::Bytes(b"Foo".to_vec());
+
+        // The default tag leaves the value untagged
+        assert_eq!(bytes, TlvValue::tagged(0x0f, bytes.clone()).unwrap());
+
+        assert!(TlvValue::tagged(0x10, bytes.clone()).is_err());
+        let tagged = TlvValue::tagged(0x02, bytes.clone()).unwrap();
+        assert!(TlvValue::tagged(0x03, tagged.clone()).is_err());
+
+        let v = tagged.to_vec().unwrap();
+        assert_eq!(0x21, v[0]);
+        assert_eq!(tagged, TlvValue::parse(v.as_slice(), 8).unwrap());
+
+        // Values built without the constructor are rejected when encoded
+        let default = TlvValue::Tagged(0x0f, Box::new(bytes.clone()));
+        assert!(default.to_vec().is_err());
+        let nested = TlvValue::Tagged(0x03, Box::new(tagged));
+        assert!(nested.to_vec().is_err());
+        let list = TlvValue::List(vec![bytes.clone(), nested]);
+        assert!(list.to_vec().is_err());
+
+        // The tags of compressed records and packed sequences are reserved
+        assert!(TlvValue::tagged(0x0d, bytes.clone()).is_err());
+        assert!(TlvValue::tagged(0x0e, bytes.clone()).is_err());
+        let compressed = TlvValue::Tagged(0x0d, Box::new(bytes.clone()));
+        assert!(compressed.to_vec().is_err());
+        let packed = TlvValue::Tagged(0x0e, Box::new(TlvValue::List(vec![bytes])));
+        assert!(packed.to_vec().is_err());
+
+        // A payload that only looks like a list with a compressed record is plain bytes
+        let v = [0xf1, 0x03, 0xd1, 0x01, 0x00];
+        assert_eq!(
+            TlvValue::Bytes(vec![0xd1, 0x01, 0x00]),
+            TlvValue::parse(&v[..], 4).unwrap()
+        );
+
+        // Packed payloads are never parsed as lists
+        let v = to_vec(&vec![0xf0u8, 0x00]).unwrap();
+        let value = TlvValue::parse(v.as_slice(), 4).unwrap();
+        assert_eq!(
+            TlvValue::Tagged(0x0e, Box::new(TlvValue::Bytes(vec![0xf0, 0x00]))),
+            value
+        );
+        assert_eq!(v, value.to_vec().unwrap());
+    }
+
+    #[test]
+    fn value_serde_roundtrip() {
+        let input = message();
+
+        let value = to_value(&input).unwrap();
+        let output: Message = from_value(value.clone()).unwrap();
+        assert_eq!(input, output);
+
+        let mut writer = TlvWriter::new(vec![]);
+        input.serialize(&mut writer).unwrap();
+        let v = writer.into_inner();
+        assert_eq!(v, value.to_vec().unwrap());
+
+        // The heuristic may expand plain payloads into lists, but they are converted back
+        let parsed = TlvValue::parse(v.as_slice(), 16).unwrap();
+        let output: Message = from_value(parsed).unwrap();
+        assert_eq!(input, output);
+    }
+
+    #[test]
+    fn value_serde_manipulation() {
+        let mut value = to_value(&message()).unwrap();
+
+        if let TlvValue::List(fields) = &mut value {
+            fields[0] = to_value(&7u64).unwrap();
+            if let TlvValue::List(kinds) = &mut fields[2] {
+                kinds.remove(0);
+            }
+        }
+
+        let output: Message = from_value(value.clone()).unwrap();
+        let mut expected = message();
+        expected.id = 7;
+        expected.kinds.remove(0);
+        assert_eq!(expected, output);
+
+        // A struct with an extra field is rejected
+        if let TlvValue::List(fields) = &mut value {
+            fields.push(TlvValue::Bytes(vec![]));
+        }
+        assert!(from_value::<Message>(value).is_err());
+    }
+}
@@ -0,0 +1,411 @@
+use crate::packed::{visit_packed, PACKED_TAG};
+use crate::{Error, TlvValue};
+
+use std::io;
+use std::vec;
+
+use serde::de::value::U32Deserializer;
+use serde::de::{
+    DeserializeOwned, DeserializeSeed, Deserializer, EnumAccess, IntoDeserializer, MapAccess,
+    SeqAccess, VariantAccess, Visitor,
+};
+
+macro_rules! value_de_le {
+    ($m:ident, $v:ident, $t:ty) => {
+        fn $m<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+        where
+            V: Visitor<'de>,
+        {
+            let mut buf = [0x00u8; std::mem::size_of::<$t>()];
+            self.read_exact(&mut buf)?;
+            visitor.$v(<$t>::from_le_bytes(buf))
+        }
+    };
+}
+
+/// Convert a [`TlvValue`] back to the type it was created from, either by [`crate::to_value`] or
+/// by parsing the output of a [`crate::TlvWriter`].
+pub fn from_value<T>(value: TlvValue) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    T::deserialize(value)
+}
+
+impl TlvValue {
+    /// Consume the value as a plain payload. Lists are converted back to their encoded records.
+    fn into_bytes(self) -> Result<Vec<u8>, Error> {
+        match self {
+            TlvValue::Bytes(b) => Ok(b),
+            TlvValue::List(l) => l
+                .iter()
+                .map(|v| v.to_vec())
+                .collect::<Result<Vec<Vec<u8>>, Error>>()
+                .map(|v| v.concat()),
+            TlvValue::Tagged(_, v) => v.into_bytes(),
+        }
+    }
+
+    /// Consume the value as a list. Plain payloads are parsed as a sequence of records.
+    fn into_list(self) -> Result<Vec<TlvValue>, Error> {
+        match self {
+            TlvValue::Bytes(b) => TlvValue::parse_slice(b.as_slice(), 0),
+            TlvValue::List(l) => Ok(l),
+            TlvValue::Tagged(_, v) => v.into_list(),
+        }
+    }
+
+    /// Consume the value as a payload of the exact length of the provided buffer
+    fn read_exact(self, buf: &mut [u8]) -> Result<(), Error> {
+        let b = self.into_bytes()?;
+        if b.len() != buf.len() {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The payload length doesnt match the expected type",
+            )));
+        }
+
+        buf.copy_from_slice(b.as_slice());
+        Ok(())
+    }
+}
+
+/// Grant all the values of a container were consumed by the visitor
+fn check_consumed(iter: &vec::IntoIter<TlvValue>) -> Result<(), Error> {
+    if iter.len() > 0 {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The container was not fully consumed",
+        )));
+    }
+
+    Ok(())
+}
+
+fn visit_list<'de, V>(list: Vec<TlvValue>, visitor: V) -> Result<V::Value, Error>
+where
+    V: Visitor<'de>,
+{
+    let mut seq = ValueSeq {
+        iter: list.into_iter(),
+    };
+
+    let value = visitor.visit_seq(&mut seq)?;
+    check_consumed(&seq.iter)?;
+
+    Ok(value)
+}
+
+/// Sequential access to the values of a container
+struct ValueSeq {
+    iter: vec::IntoIter<TlvValue>,
+}
+
+impl<'de> SeqAccess<'de> for &mut ValueSeq {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.iter.next().map(|v| seed.deserialize(v)).transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+impl<'de> MapAccess<'de> for &mut ValueSeq {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        self.iter.next().map(|v| seed.deserialize(v)).transpose()
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let value = self.iter.next().ok_or_else(|| {
+            Error::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The map key has no value",
+            ))
+        })?;
+
+        seed.deserialize(value)
+    }
+}
+
+impl<'de> EnumAccess<'de> for ValueSeq {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(mut self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let index = self.iter.next().ok_or_else(|| {
+            Error::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The enum variant has no index",
+            ))
+        })?;
+
+        let mut buf = [0x00u8; 4];
+        index.read_exact(&mut buf)?;
+
+        let index: U32Deserializer<Error> = u32::from_le_bytes(buf).into_deserializer();
+        Ok((seed.deserialize(index)?, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for ValueSeq {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        check_consumed(&self.iter)
+    }
+
+    fn newtype_variant_seed<T>(mut self, seed: T) -> Result<T::Value, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        let value = self.iter.next().ok_or_else(|| {
+            Error::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The enum variant has no value",
+            ))
+        })?;
+
+        check_consumed(&self.iter)?;
+        seed.deserialize(value)
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visit_list(self.iter.collect(), visitor)
+    }
+
+    fn struct_variant<V>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visit_list(self.iter.collect(), visitor)
+    }
+}
+
+impl<'de> Deserializer<'de> for TlvValue {
+    type Error = Error;
+
+    /// Since the TLV format is not self-describing, plain payloads are visited as bytes, and
+    /// lists as sequences.
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            TlvValue::Bytes(b) => visitor.visit_byte_buf(b),
+            TlvValue::List(l) => visit_list(l, visitor),
+            TlvValue::Tagged(_, v) => v.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let mut buf = [0x00u8];
+        self.read_exact(&mut buf)?;
+        visitor.visit_bool(buf[0] != 0)
+    }
+
+    value_de_le!(deserialize_i8, visit_i8, i8);
+    value_de_le!(deserialize_i16, visit_i16, i16);
+    value_de_le!(deserialize_i32, visit_i32, i32);
+    value_de_le!(deserialize_i64, visit_i64, i64);
+    value_de_le!(deserialize_u8, visit_u8, u8);
+    value_de_le!(deserialize_u16, visit_u16, u16);
+    value_de_le!(deserialize_u32, visit_u32, u32);
+    value_de_le!(deserialize_u64, visit_u64, u64);
+    value_de_le!(deserialize_f32, visit_f32, f32);
+    value_de_le!(deserialize_f64, visit_f64, f64);
+
+    fn deserialize_char<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let mut buf = [0x00u8];
+        self.read_exact(&mut buf)?;
+        visitor.visit_char(char::from(buf[0]))
+    }
+
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let text = String::from_utf8(self.into_bytes()?)
+            .map_err(|e| Error::Io(io::Error::new(io::ErrorKind::InvalidData, e.utf8_error())))?;
+
+        visitor.visit_string(text)
+    }
+
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_byte_buf(self.into_bytes()?)
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_byte_buf(self.into_bytes()?)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let mut list = self.into_list()?;
+        match list.len() {
+            0 => visitor.visit_none(),
+            1 => visitor.visit_some(list.remove(0)),
+            _ => Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "An option must contain at most a single record",
+            ))),
+        }
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if let TlvValue::Tagged(PACKED_TAG, v) = self {
+            return visit_packed(v.into_bytes()?.as_slice(), visitor);
+        }
+
+        visit_list(self.into_list()?, visitor)
+    }
+
+    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visit_list(self.into_list()?, visitor)
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visit_list(self.into_list()?, visitor)
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let mut map = ValueSeq {
+            iter: self.into_list()?.into_iter(),
+        };
+
+        let value = visitor.visit_map(&mut map)?;
+        check_consumed(&map.iter)?;
+
+        Ok(value)
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visit_list(self.into_list()?, visitor)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_enum(ValueSeq {
+            iter: self.into_list()?.into_iter(),
+        })
+    }
+
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+}
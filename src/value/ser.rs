@@ -0,0 +1,346 @@
+use crate::packed::{unpack, Element, Packing, PACKED_TAG};
+use crate::{Error, TlvValue};
+
+use serde::ser::{
+    SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple,
+    SerializeTupleStruct, SerializeTupleVariant,
+};
+use serde::Serialize;
+
+macro_rules! value_ser_trait {
+    ($t:ty,$m:ident) => {
+        impl $t for ValueCompound {
+            type Ok = TlvValue;
+            type Error = Error;
+
+            fn $m<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+            where
+                T: Serialize,
+            {
+                self.list.push(value.serialize(ValueSerializer)?);
+                Ok(())
+            }
+
+            fn end(self) -> Result<Self::Ok, Self::Error> {
+                Ok(TlvValue::List(self.list))
+            }
+        }
+    };
+}
+
+macro_rules! value_ser_trait_two {
+    ($t:ty,$m:ident,$m2:ident) => {
+        impl $t for ValueCompound {
+            type Ok = TlvValue;
+            type Error = Error;
+
+            fn $m<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+            where
+                T: Serialize,
+            {
+                self.list.push(value.serialize(ValueSerializer)?);
+                Ok(())
+            }
+
+            fn $m2<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+            where
+                T: Serialize,
+            {
+                self.list.push(value.serialize(ValueSerializer)?);
+                Ok(())
+            }
+
+            fn end(self) -> Result<Self::Ok, Self::Error> {
+                Ok(TlvValue::List(self.list))
+            }
+        }
+    };
+}
+
+macro_rules! value_ser_trait_field {
+    ($t:ty,$m:ident) => {
+        impl $t for ValueCompound {
+            type Ok = TlvValue;
+            type Error = Error;
+
+            fn $m<T: ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<(), Self::Error>
+            where
+                T: Serialize,
+            {
+                self.list.push(value.serialize(ValueSerializer)?);
+                Ok(())
+            }
+
+            fn end(self) -> Result<Self::Ok, Self::Error> {
+                Ok(TlvValue::List(self.list))
+            }
+        }
+    };
+}
+
+/// Convert the provided value to a [`TlvValue`], with the same structure it would have when
+/// serialized by a [`crate::TlvWriter`].
+pub fn to_value<T: ?Sized>(value: &T) -> Result<TlvValue, Error>
+where
+    T: Serialize,
+{
+    value.serialize(ValueSerializer)
+}
+
+/// [`serde::Serializer`] that builds a [`TlvValue`]
+struct ValueSerializer;
+
+/// Accumulate the values of a container
+struct ValueCompound {
+    list: Vec<TlvValue>,
+    packing: Packing,
+    packed: Vec<u8>,
+}
+
+impl ValueCompound {
+    fn new() -> Self {
+        ValueCompound {
+            list: vec![],
+            packing: Packing::Records,
+            packed: vec![],
+        }
+    }
+
+    /// Container of a sequence, that is packed if its elements are numeric
+    fn seq() -> Self {
+        ValueCompound {
+            packing: Packing::Undecided,
+            ..ValueCompound::new()
+        }
+    }
+
+    /// Container of an enum variant, with the variant index as its first value
+    fn variant(variant_index: u32) -> Self {
+        ValueCompound {
+            list: vec![variant(variant_index)],
+            ..ValueCompound::new()
+        }
+    }
+}
+
+fn bytes(v: &[u8]) -> Result<TlvValue, Error> {
+    Ok(TlvValue::Bytes(v.to_vec()))
+}
+
+fn variant(variant_index: u32) -> TlvValue {
+    TlvValue::Bytes(variant_index.to_le_bytes().to_vec())
+}
+
+/// Packed sequences are represented as a tagged plain payload
+impl SerializeSeq for ValueCompound {
+    type Ok = TlvValue;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize,
+    {
+        match self.packing.pack(value) {
+            Element::Packed(p) => self.packed.extend_from_slice(p.as_slice()),
+            Element::Record => self.list.push(value.serialize(ValueSerializer)?),
+            Element::Unpack(width, kind) => {
+                for p in unpack(self.packed.as_slice(), width, kind) {
+                    self.list.push(p.serialize(ValueSerializer)?);
+                }
+
+                self.packed.clear();
+                self.list.push(value.serialize(ValueSerializer)?);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        if self.packing.is_packed() {
+            return Ok(TlvValue::Tagged(
+                PACKED_TAG,
+                Box::new(TlvValue::Bytes(self.packed)),
+            ));
+        }
+
+        Ok(TlvValue::List(self.list))
+    }
+}
+value_ser_trait!(SerializeTuple, serialize_element);
+value_ser_trait!(SerializeTupleStruct, serialize_field);
+value_ser_trait!(SerializeTupleVariant, serialize_field);
+value_ser_trait_two!(SerializeMap, serialize_key, serialize_value);
+value_ser_trait_field!(SerializeStruct, serialize_field);
+value_ser_trait_field!(SerializeStructVariant, serialize_field);
+
+impl serde::Serializer for ValueSerializer {
+    type Ok = TlvValue;
+    type Error = Error;
+    type SerializeSeq = ValueCompound;
+    type SerializeTuple = ValueCompound;
+    type SerializeTupleStruct = ValueCompound;
+    type SerializeTupleVariant = ValueCompound;
+    type SerializeMap = ValueCompound;
+    type SerializeStruct = ValueCompound;
+    type SerializeStructVariant = ValueCompound;
+
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
+        bytes(&[if v { 0x01u8 } else { 0x00u8 }])
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
+        bytes(&v.to_le_bytes()[..])
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
+        bytes(&v.to_le_bytes()[..])
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
+        bytes(&v.to_le_bytes()[..])
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
+        bytes(&v.to_le_bytes()[..])
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
+        bytes(&v.to_le_bytes()[..])
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
+        bytes(&v.to_le_bytes()[..])
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
+        bytes(&v.to_le_bytes()[..])
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
+        bytes(&v.to_le_bytes()[..])
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
+        bytes(&v.to_le_bytes()[..])
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
+        bytes(&v.to_le_bytes()[..])
+    }
+
+    fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
+        bytes(&[v as u8])
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
+        bytes(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
+        bytes(v)
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        Ok(TlvValue::List(vec![]))
+    }
+
+    fn serialize_some<T: ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error>
+    where
+        T: Serialize,
+    {
+        Ok(TlvValue::List(vec![value.serialize(self)?]))
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+        bytes(&[])
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
+        bytes(&[])
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<Self::Ok, Self::Error> {
+        Ok(TlvValue::List(vec![variant(variant_index)]))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error>
+    where
+        T: Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error>
+    where
+        T: Serialize,
+    {
+        Ok(TlvValue::List(vec![
+            variant(variant_index),
+            value.serialize(self)?,
+        ]))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        Ok(ValueCompound::seq())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        Ok(ValueCompound::new())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        Ok(ValueCompound::new())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        Ok(ValueCompound::variant(variant_index))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        Ok(ValueCompound::new())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        Ok(ValueCompound::new())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        Ok(ValueCompound::variant(variant_index))
+    }
+}
@@ -1,7 +1,14 @@
-use crate::Error;
+#[cfg(feature = "compression")]
+use crate::compress::{compress, COMPRESSED_TAG};
+use crate::int::EncodedInt;
+use crate::packed::{unpack, Element, Numeric, Packing, PACKED_TAG};
+use crate::set::SET_ELEMENT;
+use crate::size::{size_with, Layout};
+use crate::{header_len, Error, IntEncoding, TlvEncode, TlvListWriter};
 
 use std::convert::TryInto;
-use std::io::{self, Write};
+use std::io::{self, IoSlice, SeekFrom, Write};
+use std::vec;
 
 use serde::ser::{
     SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple,
@@ -9,7 +16,7 @@ use serde::ser::{
 };
 use serde::Serialize;
 
-macro_rules! implemented_ser_trait_unimplemented {
+macro_rules! implemented_ser_trait {
     ($t:ty,$m:ident) => {
         impl<'a, W> $t for &'a mut TlvWriter<W>
         where
@@ -22,19 +29,21 @@ macro_rules! implemented_ser_trait_unimplemented {
             where
                 T: Serialize,
             {
-                let r: &mut TlvWriter<W> = *self;
-                value.serialize(r)
+                self.within(|r| {
+                    r.field = r.compact;
+                    value.serialize(r)
+                })
             }
 
             fn end(self) -> Result<Self::Ok, Self::Error> {
-                Ok(())
+                self.end_container()
             }
         }
     };
 }
 
-macro_rules! implemented_ser_trait_unimplemented_two {
-    ($t:ty,$m:ident,$m2:ident) => {
+macro_rules! implemented_ser_trait_field {
+    ($t:ty,$m:ident) => {
         impl<'a, W> $t for &'a mut TlvWriter<W>
         where
             W: io::Write,
@@ -42,57 +51,207 @@ macro_rules! implemented_ser_trait_unimplemented_two {
             type Ok = ();
             type Error = Error;
 
-            fn $m<T: ?Sized>(&mut self, _value: &T) -> Result<(), Self::Error>
+            fn $m<T: ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<(), Self::Error>
             where
                 T: Serialize,
             {
-                unimplemented!()
-            }
-
-            fn $m2<T: ?Sized>(&mut self, _value: &T) -> Result<(), Self::Error>
-            where
-                T: Serialize,
-            {
-                unimplemented!()
+                self.within(|r| {
+                    r.field = r.compact;
+                    value.serialize(r)
+                })
             }
 
             fn end(self) -> Result<Self::Ok, Self::Error> {
-                unimplemented!()
+                self.end_container()
             }
         }
     };
 }
 
-macro_rules! implemented_ser_trait_unimplemented_field {
-    ($t:ty,$m:ident) => {
-        impl<'a, W> $t for &'a mut TlvWriter<W>
-        where
-            W: io::Write,
-        {
-            type Ok = ();
-            type Error = Error;
+/// Amount of bytes reserved for the header of a record of unknown length
+pub(crate) const RESERVED_HEADER: usize = 9;
 
-            fn $m<T: ?Sized>(&mut self, _key: &'static str, _value: &T) -> Result<(), Self::Error>
-            where
-                T: Serialize,
-            {
-                unimplemented!()
-            }
+/// Encode the type and length of a payload of the provided length, and return the header buffer
+/// with the amount of bytes of it that compose the header.
+pub(crate) fn encode_header(len: usize) -> ([u8; 9], usize) {
+    let mut header = [0x00u8; 9];
 
-            fn end(self) -> Result<Self::Ok, Self::Error> {
-                unimplemented!()
-            }
-        }
-    };
+    // The TLV type will be 0xf`x`, where `x` is the amount of bytes that will be used by the
+    // length. If the buffer is empty, the type should be 0xf0
+    //
+    // Therefore, `len_mask` will hold this amount of bytes.
+    let len_mask = header_len(len) - 1;
+
+    // The TLV length will be little-endian format
+    header[0] = 0xf0 | len_mask as u8;
+    header[1..=len_mask].copy_from_slice(&(len as u64).to_le_bytes()[..len_mask]);
+
+    (header, len_mask + 1)
+}
+
+/// Append [`RESERVED_HEADER`] bytes to the buffer, and return the offset of the reserved header
+pub(crate) fn reserve_header(buf: &mut Vec<u8>) -> usize {
+    let start = buf.len();
+    buf.extend_from_slice(&[0x00u8; RESERVED_HEADER]);
+
+    start
+}
+
+/// Write the header of the payload that follows the reserved header at `start`, aligned to the
+/// end of the reserved room, and return the offset where the record begins.
+pub(crate) fn seal_header(buf: &mut [u8], start: usize) -> usize {
+    let payload = start + RESERVED_HEADER;
+
+    let (header, header_len) = encode_header(buf.len() - payload);
+    let header_start = payload - header_len;
+    buf[header_start..payload].copy_from_slice(&header[..header_len]);
+
+    header_start
+}
+
+/// Sort the entries that span from the first key to the end of the buffer by the bytes of their
+/// keys, failing if any key is duplicated
+fn sort_entries(buf: &mut [u8], keys: &[(usize, usize)]) -> Result<(), Error> {
+    let end = buf.len();
+
+    let mut entries: Vec<(&[u8], &[u8])> = keys
+        .iter()
+        .enumerate()
+        .map(|(i, (start, key_end))| {
+            let next = keys.get(i + 1).map(|k| k.0).unwrap_or(end);
+            (&buf[*start..*key_end], &buf[*start..next])
+        })
+        .collect();
+
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    if entries.windows(2).any(|e| e[0].0 == e[1].0) {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The keys of a canonical container must be unique",
+        )));
+    }
+
+    let sorted: Vec<u8> = entries.iter().flat_map(|e| e.1.iter().copied()).collect();
+    buf[keys[0].0..].copy_from_slice(sorted.as_slice());
+
+    Ok(())
+}
+
+/// Serialize the provided value in TLV format to the writer
+pub fn to_writer<W, T: ?Sized>(writer: W, value: &T) -> Result<(), Error>
+where
+    W: io::Write,
+    T: Serialize,
+{
+    value.serialize(&mut TlvWriter::new(writer))
+}
+
+/// Serialize the provided value in TLV format to a [`Vec`]
+pub fn to_vec<T: ?Sized>(value: &T) -> Result<Vec<u8>, Error>
+where
+    T: Serialize,
+{
+    let mut v = vec![];
+    to_writer(&mut v, value)?;
+
+    Ok(v)
 }
 
 /// Optionally consumes an implementation of [`io::Write`], and provides an adapter to convert
 /// slices of bytes to TLV format, and output the result to the writer.
+///
+/// When used as a [`serde::Serializer`], containers are buffered until the outermost one is
+/// complete, since the header of a record must contain the length of its payload. Enum variants
+/// are containers whose first record is the variant index, followed by the variant fields.
+/// Options are containers that are empty for [`None`], and hold the record of the value for
+/// [`Some`].
+///
+/// Sequences of numeric values, such as `Vec<u8>` or `Vec<u64>`, are packed into a single record
+/// of type `0xe`x`` whose payload is composed by the consecutive little-endian values.
+///
+/// The payload of integer records follows the [`IntEncoding`] of the writer, that is fixed-width
+/// little-endian by default.
+///
+/// In the compact layout, enabled by [`TlvWriter::set_compact_structs`], the fixed-size primitive
+/// fields of structs and tuples are written back-to-back without headers. Since such fields are
+/// not self-describing, the reader must be configured with the same layout.
+///
+/// A canonical writer, enabled by [`TlvWriter::set_canonical`], sorts the entries of maps and the
+/// elements of sets marked by [`crate::set`] by their encoded bytes, so equal values always
+/// produce the same output regardless of the iteration order.
 pub struct TlvWriter<W>
 where
     W: io::Write,
 {
     writer: W,
+    buf: Vec<u8>,
+    open: Vec<Container>,
+    ints: IntEncoding,
+    compact: bool,
+    field: bool,
+    canonical: bool,
+    /// Layouts of the containers yet to be opened, if they were computed in advance
+    layouts: Option<vec::IntoIter<Layout>>,
+    /// Bytes written to the inner writer, to check the payloads of the streamed containers
+    /// against their layouts
+    written: usize,
+    #[cfg(feature = "compression")]
+    compression: Option<usize>,
+}
+
+/// Container open in the buffer of a [`TlvWriter`]
+struct Container {
+    /// Offset of the reserved header
+    start: usize,
+    packing: Packing,
+    /// Offsets of the encoded keys of a canonical container. Every entry spans from its key to
+    /// the key of the next entry.
+    keys: Vec<(usize, usize)>,
+    /// The header was written ahead of the payload, so the records are not buffered
+    streamed: bool,
+    /// Count of written bytes at which the payload of a streamed container must end
+    end: usize,
+}
+
+/// Destination of the records of a [`TlvWriter`], that counts the bytes written to the inner
+/// writer
+pub(crate) enum Sink<'a, W> {
+    Buffer(&'a mut Vec<u8>),
+    Writer(&'a mut W, &'a mut usize),
+}
+
+impl<'a, W> io::Write for Sink<'a, W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::Buffer(b) => b.write(buf),
+            Sink::Writer(w, written) => {
+                let n = w.write(buf)?;
+                **written += n;
+                Ok(n)
+            }
+        }
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        match self {
+            Sink::Buffer(b) => b.write_vectored(bufs),
+            Sink::Writer(w, written) => {
+                let n = w.write_vectored(bufs)?;
+                **written += n;
+                Ok(n)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Buffer(b) => b.flush(),
+            Sink::Writer(w, _) => w.flush(),
+        }
+    }
 }
 
 impl<W> TlvWriter<W>
@@ -101,7 +260,67 @@ where
 {
     /// [`TlvWriter`] constructor
     pub fn new(writer: W) -> Self {
-        TlvWriter { writer }
+        TlvWriter::with_int_encoding(writer, IntEncoding::default())
+    }
+
+    /// [`TlvWriter`] constructor with the provided encoding of integers
+    pub fn with_int_encoding(writer: W, ints: IntEncoding) -> Self {
+        TlvWriter {
+            writer,
+            buf: vec![],
+            open: vec![],
+            ints,
+            compact: false,
+            field: false,
+            canonical: false,
+            layouts: None,
+            written: 0,
+            #[cfg(feature = "compression")]
+            compression: None,
+        }
+    }
+
+    /// Compress the payloads of records written by [`TlvWriter::write_record`] whose length is at
+    /// least the provided threshold, as long as they get smaller. Compressed records have the
+    /// type 0xd`x`, and are decompressed transparently by the [`crate::TlvReader`].
+    ///
+    /// Containers and packed sequences are not compressed, but the records they contain are.
+    /// [`TlvWriter::serialized_size`] is an upper bound of the compressed output.
+    #[cfg(feature = "compression")]
+    pub fn set_compression(&mut self, threshold: Option<usize>) {
+        self.compression = threshold;
+    }
+
+    /// Sort the entries of maps and the elements of sets by their encoded bytes
+    pub fn set_canonical(&mut self, canonical: bool) {
+        self.canonical = canonical;
+    }
+
+    /// Write the fixed-size primitive fields of structs and tuples without headers. The struct
+    /// containers themselves are still regular records.
+    pub fn set_compact_structs(&mut self, compact: bool) {
+        self.compact = compact;
+    }
+
+    /// Write the headers of containers ahead of their payload, with the provided layouts of the
+    /// next value to be serialized, in the order its containers are opened. Only the containers
+    /// whose records are sorted, and the ones nested into them, are buffered.
+    pub(crate) fn set_layouts(&mut self, layouts: Vec<Layout>) {
+        self.layouts = Some(layouts.into_iter());
+    }
+
+    /// Return the exact amount of bytes the provided value will occupy when serialized by this
+    /// writer, without writing any bytes.
+    pub fn serialized_size<T: ?Sized>(&self, value: &T) -> Result<usize, Error>
+    where
+        T: Serialize,
+    {
+        size_with(value, self.ints, self.compact)
+    }
+
+    /// Encoding of the integers written by this writer
+    pub fn int_encoding(&self) -> IntEncoding {
+        self.ints
     }
 
     /// Consumes self, and return the inner writer
@@ -109,98 +328,537 @@ where
         self.writer
     }
 
-    /// Convert the provided slice of bytes to TLV format, and write the TL to the writer
-    pub fn bytes_len_to_writer(writer: W, mut len: usize) -> Result<usize, Error> {
-        let mut writer = writer;
+    /// Write the type and length of a payload of the provided length to the writer, and return the
+    /// amount of bytes written.
+    pub fn bytes_len_to_writer(mut writer: W, len: usize) -> Result<usize, Error> {
+        let (header, header_len) = encode_header(len);
+        writer.write_all(&header[..header_len])?;
+
+        Ok(header_len)
+    }
+
+    /// Convert the provided slice of bytes to TLV format, output the result to the provided
+    /// writer, and return the amount of bytes written, header included.
+    ///
+    /// The header and the payload are sent with vectored writes, and short writes are retried
+    /// until the whole record is written.
+    pub fn bytes_to_writer(mut writer: W, buf: &[u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return TlvWriter::bytes_len_to_writer(writer, 0);
+        }
+
+        let (header, header_len) = encode_header(buf.len());
+        let header = &header[..header_len];
+        let total = header_len + buf.len();
+
+        // The slices are advanced by hand, since the written bytes may end in either of them
+        let mut written = 0;
+        while written < total {
+            let result = if written < header_len {
+                writer.write_vectored(&[IoSlice::new(&header[written..]), IoSlice::new(buf)])
+            } else {
+                writer.write(&buf[written - header_len..])
+            };
+
+            match result {
+                Ok(0) => {
+                    return Err(Error::Io(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "The writer didnt accept all the bytes of the TLV record",
+                    )))
+                }
+                Ok(n) => written += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(header_len + buf.len())
+    }
+
+    /// Convert the provided slice of bytes to a single TLV record, write it to the inner writer,
+    /// and return the amount of bytes written, header included.
+    pub fn write_record(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        self.field = false;
+
+        #[cfg(feature = "compression")]
+        {
+            if let Some(compressed) = self
+                .compression
+                .filter(|t| buf.len() >= *t)
+                .and_then(|_| compress(buf))
+            {
+                return self.write_compressed(compressed.as_slice());
+            }
+        }
+
+        TlvWriter::bytes_to_writer(self.sink(), buf)
+    }
+
+    #[cfg(feature = "compression")]
+    fn write_compressed(&mut self, payload: &[u8]) -> Result<usize, Error> {
+        let (mut header, header_len) = encode_header(payload.len());
+        header[0] = (COMPRESSED_TAG << 4) | (header[0] & 0x0f);
+
+        let mut sink = self.sink();
+        sink.write_all(&header[..header_len])?;
+        sink.write_all(payload)?;
 
-        // If the buffer is empty, the type should be 0xf0
-        if len == 0 {
-            writer.write(&[0xf0])?;
-            return Ok(0);
+        Ok(header_len + payload.len())
+    }
+
+    /// Write the payload of a primitive. If it is fixed-size and a field of a compact struct, the
+    /// header is omitted.
+    fn write_primitive(&mut self, buf: &[u8], fixed: bool) -> Result<(), Error> {
+        if fixed && self.field {
+            self.field = false;
+            self.sink().write_all(buf)?;
+            return Ok(());
         }
 
-        // The TLV length will be little-endian format
-        let buf_len = len.to_le_bytes();
+        self.write_record(buf).map(|_| ())
+    }
 
-        // The TLV type will be 0xf`x`, where `x` is the amount of bytes that will be used by the
-        // length.
-        //
-        // Therefore, `len_mask` will hold this amount of bytes. The bitwise operators will allow
-        // a fast definition for that.
-        let mut len_mask = 0x01;
-        len >>= 8;
-        while len > 0 {
-            len_mask <<= 1;
-            len >>= 8;
+    /// Destination of the records: the buffer while a buffered serde container is open, or the
+    /// inner writer otherwise.
+    pub(crate) fn sink(&mut self) -> Sink<'_, W> {
+        if self.is_buffered() {
+            Sink::Buffer(&mut self.buf)
+        } else {
+            Sink::Writer(&mut self.writer, &mut self.written)
         }
+    }
 
-        writer.write(&[0xf0 | len_mask])?;
-        Ok(writer.write(&buf_len[..len_mask as usize])?)
+    /// Check if the records are buffered, that is the case while the innermost open container is
+    /// not streamed. A container nested into a buffered one is always buffered as well.
+    fn is_buffered(&self) -> bool {
+        self.open.last().map(|c| !c.streamed).unwrap_or(false)
     }
 
-    /// Convert the provided slice of bytes to TLV format, output the result to the provided
-    /// writer, and return the amount of bytes written.
-    pub fn bytes_to_writer(mut writer: W, buf: &[u8]) -> Result<usize, Error> {
-        TlvWriter::bytes_len_to_writer(&mut writer, buf.len())?;
-        Ok(writer.write(buf)?)
+    /// Forward the flush call to the inner writer
+    pub fn flush(&mut self) -> Result<(), Error> {
+        Ok(self.writer.flush()?)
     }
 
-    /// Append the provided usize to the writer in TLV format
+    /// Append the provided usize to the writer in TLV format.
+    ///
+    /// The value is written as a [`u64`], so the output doesn't depend on the target platform.
     pub fn write_usize(&mut self, n: usize) -> Result<usize, Error> {
-        let n = n.to_le_bytes();
+        self.write_unsigned(n as u128, std::mem::size_of::<u64>())
+    }
 
-        TlvWriter::bytes_to_writer(&mut self.writer, &n[..])
+    /// Append the provided isize to the writer in TLV format.
+    ///
+    /// The value is written as an [`i64`], so the output doesn't depend on the target platform.
+    pub fn write_isize(&mut self, n: isize) -> Result<usize, Error> {
+        self.write_signed(n as i128, std::mem::size_of::<i64>())
     }
 
-    /// Write a list of serializable items
-    pub fn write_list<L: AsRef<[u8]>>(&mut self, list: &[L]) -> Result<usize, Error> {
-        let buf: Vec<u8> = vec![];
-        let mut writer = TlvWriter::new(buf);
+    /// Write an unsigned integer of `width` bytes as a single record, following the encoding of
+    /// the writer
+    pub(crate) fn write_unsigned(&mut self, v: u128, width: usize) -> Result<usize, Error> {
+        let n = self.ints.encode_unsigned(v, width);
+        self.write_record(n.as_slice())
+    }
 
-        for item in list {
-            writer.write(item.as_ref())?;
+    /// Write an integer of a serde value, that is fixed-size unless encoded as varint
+    fn write_int(&mut self, n: EncodedInt) -> Result<(), Error> {
+        let fixed = self.ints != IntEncoding::Varint;
+        self.write_primitive(n.as_slice(), fixed)
+    }
+
+    /// Write a signed integer of `width` bytes as a single record, following the encoding of the
+    /// writer
+    pub(crate) fn write_signed(&mut self, v: i128, width: usize) -> Result<usize, Error> {
+        let n = self.ints.encode_signed(v, width);
+        self.write_record(n.as_slice())
+    }
+
+    /// Open a container, reserving its header in the buffer. The records written until the
+    /// matching [`TlvWriter::end_container`] compose its payload.
+    pub fn begin_container(&mut self) -> Result<(), Error> {
+        self.open_container(Packing::Records)
+    }
+
+    fn open_container(&mut self, packing: Packing) -> Result<(), Error> {
+        self.field = false;
+
+        // The layouts are consumed by every container, so they stay aligned with the value
+        let layout = self.layouts.as_mut().and_then(|l| l.next());
+        let (streamed, packing) = match layout {
+            Some(l) if !l.keyed && !self.is_buffered() => {
+                let (mut header, header_len) = encode_header(l.len);
+                if l.packed {
+                    header[0] = (PACKED_TAG << 4) | (header[0] & 0x0f);
+                }
+
+                self.writer.write_all(&header[..header_len])?;
+                self.written += header_len;
+
+                // A streamed sequence can't fall back to records once its header is written
+                if l.packed {
+                    (true, packing)
+                } else {
+                    (true, Packing::Records)
+                }
+            }
+            _ => (false, packing),
+        };
+
+        let start = if streamed {
+            self.buf.len()
+        } else {
+            reserve_header(&mut self.buf)
+        };
+
+        self.open.push(Container {
+            start,
+            packing,
+            keys: vec![],
+            streamed,
+            end: self.written + layout.map(|l| l.len).unwrap_or(0),
+        });
+
+        Ok(())
+    }
+
+    /// Write a key of the innermost container, and record its offsets to sort the entries once
+    /// the container is closed
+    fn write_key<F>(&mut self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut Self) -> Result<(), Error>,
+    {
+        let start = self.buf.len();
+        f(self)?;
+        let end = self.buf.len();
+
+        if let Some(c) = self.open.last_mut() {
+            c.keys.push((start, end));
+        }
+
+        Ok(())
+    }
+
+    /// Close the innermost container. If it is the outermost one, the buffer is written to the
+    /// inner writer.
+    pub fn end_container(&mut self) -> Result<(), Error> {
+        let Container {
+            start,
+            packing,
+            keys,
+            streamed,
+            end,
+        } = self.open.pop().ok_or_else(|| {
+            Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "There is no open container to be closed",
+            ))
+        })?;
+
+        // The serialization of the value must match the one its layouts were computed from
+        if streamed {
+            if self.written != end {
+                self.field = false;
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "The payload of the container doesnt match its computed layout",
+                )));
+            }
+
+            return Ok(());
+        }
+
+        if !keys.is_empty() {
+            if let Err(e) = sort_entries(&mut self.buf, keys.as_slice()) {
+                self.buf.truncate(start);
+                self.field = false;
+                return Err(e);
+            }
+        }
+
+        let header_start = seal_header(&mut self.buf, start);
+        if packing.is_packed() {
+            self.buf[header_start] = (PACKED_TAG << 4) | (self.buf[header_start] & 0x0f);
+        }
+
+        if !self.is_buffered() {
+            let result = self.writer.write_all(&self.buf[header_start..]);
+            self.written += self.buf.len() - header_start;
+            self.buf.clear();
+            result?;
+        } else {
+            self.buf.drain(start..header_start);
+        }
+
+        Ok(())
+    }
+
+    /// Open the container of an enum variant, and write the variant index as its first record of
+    /// 4 bytes. The container is closed by [`TlvWriter::end_container`].
+    pub fn begin_variant(&mut self, variant_index: u32) -> Result<(), Error> {
+        self.begin_container()?;
+        self.write_record(&variant_index.to_le_bytes()[..])
+            .map(|_| ())
+    }
+
+    /// Write a container whose records are written by `f`. If `f` fails, the container is
+    /// discarded, so the writer can still be used.
+    pub fn write_container<F>(&mut self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut Self) -> Result<(), Error>,
+    {
+        self.begin_container()?;
+        self.within(f)?;
+        self.end_container()
+    }
+
+    /// Write the container of an enum variant whose fields are written by `f`. If `f` fails, the
+    /// container is discarded, so the writer can still be used.
+    pub fn write_variant<F>(&mut self, variant_index: u32, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut Self) -> Result<(), Error>,
+    {
+        self.begin_variant(variant_index)?;
+        self.within(f)?;
+        self.end_container()
+    }
+
+    /// Write into the innermost open container. If `f` fails, the container is dropped from the
+    /// buffer, along with any container left open inside of it.
+    fn within<T, F>(&mut self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Self) -> Result<T, Error>,
+    {
+        let depth = self.open.len().saturating_sub(1);
+        let result = f(self);
+
+        if result.is_err() {
+            if let Some(c) = self.open.get(depth) {
+                self.buf.truncate(c.start);
+            }
+
+            self.open.truncate(depth);
+            self.field = false;
+        }
+
+        result
+    }
+
+    /// Rewrite the packed elements of the innermost container as records, once an element can't
+    /// be packed along them
+    fn unpack(&mut self, width: usize, kind: Numeric) -> Result<(), Error> {
+        let payload = match self.open.last() {
+            Some(c) if !c.streamed => c.start + RESERVED_HEADER,
+            _ => {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "The elements of a streamed packed sequence must be numeric values of the same type",
+                )))
+            }
+        };
+
+        let packed = self.buf.split_off(payload);
+        for p in unpack(packed.as_slice(), width, kind) {
+            p.serialize(&mut *self)?;
         }
 
-        let buf = writer.into_inner();
-        Ok(self.write(buf.as_slice())?)
+        Ok(())
+    }
+
+    /// Write the provided payload of consecutive little-endian numeric values as a single packed
+    /// sequence record
+    pub(crate) fn write_packed(&mut self, payload: &[u8]) -> Result<(), Error> {
+        let (mut header, header_len) = encode_header(payload.len());
+        header[0] = (PACKED_TAG << 4) | (header[0] & 0x0f);
+
+        let mut sink = self.sink();
+        sink.write_all(&header[..header_len])?;
+        sink.write_all(payload)?;
+
+        Ok(())
+    }
+
+    /// Write the provided value via its [`TlvEncode`] implementation
+    pub fn encode<T: TlvEncode + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        value.encode(self)
     }
 
-    /// Write a list of falible serializable items
+    /// Begin a list, and return a [`TlvListWriter`] to append its items.
+    ///
+    /// The list, and every list nested into it, share a single buffer that is written to the inner
+    /// writer once the outermost list is closed. Inside an open container, the list is written
+    /// into the buffer of the container instead.
+    pub fn begin_list(&mut self) -> TlvListWriter<'_, W> {
+        self.field = false;
+        if self.is_buffered() {
+            return TlvListWriter::buffered(&mut self.buf);
+        }
+
+        TlvListWriter::root(&mut self.writer)
+    }
+
+    /// Write a list of serializable items
+    pub fn write_list<I>(&mut self, list: I) -> Result<usize, Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut writer = self.begin_list();
+        writer.extend(list)?;
+        writer.finish()
+    }
+
+    /// Write a list of falible serializable items.
+    ///
+    /// If any item fails to be converted, nothing is written.
     pub fn try_write_list<L: Clone + TryInto<Vec<u8>, Error = Error>>(
         &mut self,
         list: &[L],
     ) -> Result<usize, Error> {
-        let buf: Vec<u8> = vec![];
-        let mut writer = TlvWriter::new(buf);
+        let mut writer = self.begin_list();
 
         for item in list {
-            let v: Vec<u8> = item.clone().try_into()?;
-            writer.write(v.as_slice())?;
+            let v: Vec<u8> = match item.clone().try_into() {
+                Ok(v) => v,
+                Err(e) => {
+                    writer.discard();
+                    return Err(e);
+                }
+            };
+
+            writer.write_item(v.as_slice())?;
         }
 
-        let buf = writer.into_inner();
-        Ok(self.write(buf.as_slice())?)
+        writer.finish()
     }
 }
 
-impl<W> io::Write for TlvWriter<W>
+impl<W> TlvWriter<W>
 where
-    W: io::Write,
+    W: io::Write + io::Seek,
+{
+    /// Reserve a fixed-width header for a record of unknown length, and return a
+    /// [`TlvRecord`] to stream its payload.
+    ///
+    /// The real length will be patched into the header by [`TlvRecord::finish`]. Since the header
+    /// is patched in the underlying writer, a record can't be begun inside an open container.
+    pub fn begin_record(&mut self) -> Result<TlvRecord<'_, W>, Error> {
+        if !self.open.is_empty() {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "A record of unknown length can't be begun inside an open container",
+            )));
+        }
+
+        TlvRecord::begin(&mut self.writer)
+    }
+
+    /// Write a list of serializable items of unknown length, without buffering it
+    pub fn write_list_iter<I>(&mut self, list: I) -> Result<usize, Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut record = self.begin_record()?;
+
+        for item in list {
+            record.write_item(item.as_ref())?;
+        }
+
+        record.finish()
+    }
+}
+
+/// Record of unknown length, provided by [`TlvWriter::begin_record`].
+///
+/// The header is reserved with the widest length format, `0xf8`, so the payload can be streamed to
+/// the writer, and the length patched back once the record is finished.
+///
+/// The record is finished by [`TlvRecord::finish`], or automatically when dropped. Errors when
+/// finishing on drop are ignored, so [`TlvRecord::finish`] should be preferred.
+pub struct TlvRecord<'a, W>
+where
+    W: io::Write + io::Seek,
+{
+    writer: &'a mut W,
+    start: u64,
+    finished: bool,
+}
+
+impl<'a, W> TlvRecord<'a, W>
+where
+    W: io::Write + io::Seek,
+{
+    /// Length of the reserved header
+    const HEADER_LEN: u64 = 9;
+
+    fn begin(writer: &'a mut W) -> Result<Self, Error> {
+        let start = writer.stream_position()?;
+        writer.write_all(&[0xf8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00])?;
+
+        Ok(TlvRecord {
+            writer,
+            start,
+            finished: false,
+        })
+    }
+
+    /// Append the provided slice of bytes to the payload in TLV format
+    pub fn write_item(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        TlvWriter::bytes_to_writer(&mut *self.writer, buf)
+    }
+
+    /// Reserve a nested record of unknown length inside the payload of this record
+    pub fn begin_record(&mut self) -> Result<TlvRecord<'_, W>, Error> {
+        TlvRecord::begin(self.writer)
+    }
+
+    /// Patch the length of the payload into the reserved header, and return the total amount of
+    /// bytes of the record, header included.
+    pub fn finish(mut self) -> Result<usize, Error> {
+        self.close()
+    }
+
+    fn close(&mut self) -> Result<usize, Error> {
+        self.finished = true;
+
+        let end = self.writer.stream_position()?;
+        let len = end - self.start - TlvRecord::<W>::HEADER_LEN;
+
+        self.writer.seek(SeekFrom::Start(self.start + 1))?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(end))?;
+
+        Ok((end - self.start) as usize)
+    }
+}
+
+impl<'a, W> Drop for TlvRecord<'a, W>
+where
+    W: io::Write + io::Seek,
 {
-    /// The [`io::Write::write`] implementation will internally call the
-    /// [`TlvWriter::bytes_to_writer`]. Therefore, the provided buffer will first be converted to
-    /// TLV format, and then sent to the inner writer.
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.close();
+        }
+    }
+}
+
+impl<'a, W> io::Write for TlvRecord<'a, W>
+where
+    W: io::Write + io::Seek,
+{
+    /// Raw bytes written to the record are appended to its payload, without conversion
     fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
-        TlvWriter::bytes_to_writer(&mut self.writer, buf).map_err(|e| e.into())
+        self.writer.write(buf)
     }
 
-    /// The [`io::Write::flush`] implementation will just forward the call to the inner writer.
     fn flush(&mut self) -> Result<(), io::Error> {
         self.writer.flush()
     }
 }
 
-impl<'a, W> SerializeSeq for &mut TlvWriter<W>
+impl<'a, W> SerializeSeq for &'a mut TlvWriter<W>
 where
     W: io::Write,
 {
@@ -211,21 +869,67 @@ where
     where
         T: Serialize,
     {
-        let r: &mut TlvWriter<W> = *self;
-        value.serialize(r)
+        self.within(|w| {
+            let element = match w.open.last_mut() {
+                Some(c) => c.packing.pack(value),
+                None => Element::Record,
+            };
+
+            match element {
+                Element::Packed(p) => {
+                    w.sink().write_all(p.as_slice())?;
+                    Ok(())
+                }
+                Element::Record => value.serialize(w),
+                Element::Unpack(width, kind) => {
+                    w.unpack(width, kind)?;
+                    value.serialize(w)
+                }
+            }
+        })
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(())
+        self.end_container()
+    }
+}
+
+/// The entries of a canonical writer are sorted by the encoded bytes of their keys
+impl<'a, W> SerializeMap for &'a mut TlvWriter<W>
+where
+    W: io::Write,
+{
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized>(&mut self, key: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize,
+    {
+        if self.canonical {
+            return self.within(|w| w.write_key(|w| key.serialize(w)));
+        }
+
+        self.within(|w| key.serialize(w))
+    }
+
+    fn serialize_value<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize,
+    {
+        self.within(|w| value.serialize(w))
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.end_container()
     }
 }
 
-implemented_ser_trait_unimplemented!(SerializeTuple, serialize_element);
-implemented_ser_trait_unimplemented!(SerializeTupleStruct, serialize_field);
-implemented_ser_trait_unimplemented!(SerializeTupleVariant, serialize_field);
-implemented_ser_trait_unimplemented_two!(SerializeMap, serialize_key, serialize_value);
-implemented_ser_trait_unimplemented_field!(SerializeStruct, serialize_field);
-implemented_ser_trait_unimplemented_field!(SerializeStructVariant, serialize_field);
+implemented_ser_trait!(SerializeTuple, serialize_element);
+implemented_ser_trait!(SerializeTupleStruct, serialize_field);
+implemented_ser_trait!(SerializeTupleVariant, serialize_field);
+implemented_ser_trait_field!(SerializeStruct, serialize_field);
+implemented_ser_trait_field!(SerializeStructVariant, serialize_field);
 
 impl<'a, W> serde::Serializer for &'a mut TlvWriter<W>
 where
@@ -242,97 +946,130 @@ where
     type SerializeStructVariant = Self;
 
     fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
-        Ok(self.write(&[if v { 0x01u8 } else { 0x00u8 }]).map(|_| ())?)
+        self.write_primitive(&[if v { 0x01u8 } else { 0x00u8 }], true)
     }
 
     fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
-        Ok(self.write(&v.to_le_bytes()[..]).map(|_| ())?)
+        let n = self
+            .ints
+            .encode_signed(v as i128, std::mem::size_of::<i8>());
+        self.write_int(n)
     }
 
     fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
-        Ok(self.write(&v.to_le_bytes()[..]).map(|_| ())?)
+        let n = self
+            .ints
+            .encode_signed(v as i128, std::mem::size_of::<i16>());
+        self.write_int(n)
     }
 
     fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
-        Ok(self.write(&v.to_le_bytes()[..]).map(|_| ())?)
+        let n = self
+            .ints
+            .encode_signed(v as i128, std::mem::size_of::<i32>());
+        self.write_int(n)
     }
 
     fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
-        Ok(self.write(&v.to_le_bytes()[..]).map(|_| ())?)
+        let n = self
+            .ints
+            .encode_signed(v as i128, std::mem::size_of::<i64>());
+        self.write_int(n)
     }
 
     fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
-        Ok(self.write(&v.to_le_bytes()[..]).map(|_| ())?)
+        let n = self
+            .ints
+            .encode_unsigned(v as u128, std::mem::size_of::<u8>());
+        self.write_int(n)
     }
 
     fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
-        Ok(self.write(&v.to_le_bytes()[..]).map(|_| ())?)
+        let n = self
+            .ints
+            .encode_unsigned(v as u128, std::mem::size_of::<u16>());
+        self.write_int(n)
     }
 
     fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
-        Ok(self.write(&v.to_le_bytes()[..]).map(|_| ())?)
+        let n = self
+            .ints
+            .encode_unsigned(v as u128, std::mem::size_of::<u32>());
+        self.write_int(n)
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
-        Ok(self.write(&v.to_le_bytes()[..]).map(|_| ())?)
+        let n = self
+            .ints
+            .encode_unsigned(v as u128, std::mem::size_of::<u64>());
+        self.write_int(n)
     }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
-        Ok(self.write(&v.to_le_bytes()[..]).map(|_| ())?)
+        self.write_primitive(&v.to_le_bytes()[..], true)
     }
 
     fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
-        Ok(self.write(&v.to_le_bytes()[..]).map(|_| ())?)
+        self.write_primitive(&v.to_le_bytes()[..], true)
     }
 
     fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
-        Ok(self.write(&[v as u8]).map(|_| ())?)
+        self.write_primitive(&[v as u8], true)
     }
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
-        Ok(self.write(v.as_bytes()).map(|_| ())?)
+        self.write_record(v.as_bytes()).map(|_| ())
     }
 
     fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
-        Ok(self.write(v).map(|_| ())?)
+        self.write_record(v).map(|_| ())
     }
 
+    /// [`None`] is an empty container
     fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
-        Ok(self.write(&[]).map(|_| ())?)
+        self.write_container(|_| Ok(()))
     }
 
+    /// [`Some`] is a container of a single record, so values with an empty payload are still
+    /// distinguished from [`None`]
     fn serialize_some<T: ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error>
     where
         T: Serialize,
     {
-        value.serialize(self)
+        self.write_container(|w| value.serialize(w))
     }
 
     fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
-        Ok(self.write(&[]).map(|_| ())?)
+        self.write_record(&[]).map(|_| ())
     }
 
     fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
-        Ok(self.write(&[]).map(|_| ())?)
+        self.write_record(&[]).map(|_| ())
     }
 
     fn serialize_unit_variant(
         self,
         _name: &'static str,
-        _variant_index: u32,
+        variant_index: u32,
         _variant: &'static str,
     ) -> Result<Self::Ok, Self::Error> {
-        Ok(self.write(&[]).map(|_| ())?)
+        self.begin_variant(variant_index)?;
+        self.end_container()
     }
 
+    /// The elements of a set are sorted as keys by a canonical writer
     fn serialize_newtype_struct<T: ?Sized>(
         self,
-        _name: &'static str,
+        name: &'static str,
         value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
         T: Serialize,
     {
+        if name == SET_ELEMENT && self.canonical {
+            return self.write_key(|w| value.serialize(w));
+        }
+
         value.serialize(self)
     }
 
@@ -346,72 +1083,61 @@ where
     where
         T: Serialize,
     {
-        self.write(&variant_index.to_le_bytes()[..]).map(|_| ())?;
-        value.serialize(self)
+        self.write_variant(variant_index, |w| value.serialize(w))
     }
 
-    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
-        let len = len.ok_or(Error::Io(io::Error::new(
-            io::ErrorKind::Other,
-            "The leading size is mandatory",
-        )))?;
-
-        TlvWriter::bytes_len_to_writer(&mut self.writer, len)?;
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        self.open_container(Packing::Undecided)?;
         Ok(self)
     }
 
-    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
-        TlvWriter::bytes_len_to_writer(&mut self.writer, len)?;
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        self.begin_container()?;
         Ok(self)
     }
 
     fn serialize_tuple_struct(
         self,
         _name: &'static str,
-        len: usize,
+        _len: usize,
     ) -> Result<Self::SerializeTupleStruct, Self::Error> {
-        TlvWriter::bytes_len_to_writer(&mut self.writer, len)?;
+        self.begin_container()?;
         Ok(self)
     }
 
     fn serialize_tuple_variant(
         self,
         _name: &'static str,
-        _variant_index: u32,
+        variant_index: u32,
         _variant: &'static str,
-        len: usize,
+        _len: usize,
     ) -> Result<Self::SerializeTupleVariant, Self::Error> {
-        TlvWriter::bytes_len_to_writer(&mut self.writer, len)?;
+        self.begin_variant(variant_index)?;
         Ok(self)
     }
 
-    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
-        let len = len.ok_or(Error::Io(io::Error::new(
-            io::ErrorKind::Other,
-            "The leading size is mandatory",
-        )))?;
-
-        TlvWriter::bytes_len_to_writer(&mut self.writer, len)?;
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        self.begin_container()?;
         Ok(self)
     }
 
     fn serialize_struct(
         self,
         _name: &'static str,
-        len: usize,
+        _len: usize,
     ) -> Result<Self::SerializeStruct, Self::Error> {
-        TlvWriter::bytes_len_to_writer(&mut self.writer, len)?;
+        self.begin_container()?;
         Ok(self)
     }
 
     fn serialize_struct_variant(
         self,
         _name: &'static str,
-        _variant_index: u32,
+        variant_index: u32,
         _variant: &'static str,
-        len: usize,
+        _len: usize,
     ) -> Result<Self::SerializeStructVariant, Self::Error> {
-        TlvWriter::bytes_len_to_writer(&mut self.writer, len)?;
+        self.begin_variant(variant_index)?;
         Ok(self)
     }
 }
@@ -419,9 +1145,31 @@ where
 #[cfg(test)]
 mod tests {
     use crate::*;
-    use std::io::{Cursor, Write};
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+    use std::convert::TryFrom;
+    use std::io::{self, Cursor, Write};
     use std::iter;
 
+    /// Writer that accepts a single byte per call
+    struct ShortWriter(Vec<u8>);
+
+    impl io::Write for ShortWriter {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+            match buf.first() {
+                Some(b) => {
+                    self.0.push(*b);
+                    Ok(1)
+                }
+                None => Ok(0),
+            }
+        }
+
+        fn flush(&mut self) -> Result<(), io::Error> {
+            Ok(())
+        }
+    }
+
     #[test]
     fn tlv_writer() {
         let buf: Vec<u8> = iter::repeat(())
@@ -432,7 +1180,7 @@ mod tests {
 
         let cursor = Cursor::new(Vec::<u8>::new());
         let mut tlv = TlvWriter::new(cursor);
-        tlv.write(buf.as_slice()).unwrap();
+        tlv.write_record(buf.as_slice()).unwrap();
 
         let cursor = tlv.into_inner();
         let result = cursor.into_inner();
@@ -446,7 +1194,7 @@ mod tests {
     fn tlv_writer_zero() {
         let cursor = Cursor::new(Vec::<u8>::new());
         let mut tlv = TlvWriter::new(cursor);
-        tlv.write(&[][..]).unwrap();
+        tlv.write_record(&[][..]).unwrap();
 
         let cursor = tlv.into_inner();
         let result = cursor.into_inner();
@@ -464,7 +1212,7 @@ mod tests {
 
         let cursor = Cursor::new(Vec::<u8>::new());
         let mut tlv = TlvWriter::new(cursor);
-        tlv.write(buf.as_slice()).unwrap();
+        tlv.write_record(buf.as_slice()).unwrap();
 
         let cursor = tlv.into_inner();
         let result = cursor.into_inner();
@@ -484,7 +1232,7 @@ mod tests {
 
         let cursor = Cursor::new(Vec::<u8>::new());
         let mut tlv = TlvWriter::new(cursor);
-        tlv.write(buf.as_slice()).unwrap();
+        tlv.write_record(buf.as_slice()).unwrap();
 
         let cursor = tlv.into_inner();
         let result = cursor.into_inner();
@@ -493,4 +1241,434 @@ mod tests {
         assert_eq!(&[0x00u8, 0x00, 0x01, 0x00], &result[1..5]);
         assert_eq!(buf.as_slice(), &result[5..]);
     }
+
+    #[test]
+    fn tlv_writer_list_iter() {
+        let cursor = Cursor::new(Vec::<u8>::new());
+        let mut tlv = TlvWriter::new(cursor);
+        let written = tlv
+            .write_list_iter((0..1000u64).map(|i| i.to_le_bytes()))
+            .unwrap();
+
+        let mut cursor = tlv.into_inner();
+        assert_eq!(written as u64, cursor.position());
+        assert_eq!(0xf8u8, cursor.get_ref()[0]);
+
+        cursor.set_position(0);
+        let mut tlv_reader = TlvReader::new(cursor);
+        let output: Vec<u64> = tlv_reader
+            .read_list::<Vec<u8>>()
+            .unwrap()
+            .iter()
+            .map(|i| {
+                let mut n = [0x00u8; 8];
+                n.copy_from_slice(i.as_slice());
+                u64::from_le_bytes(n)
+            })
+            .collect();
+
+        assert_eq!((0..1000u64).collect::<Vec<u64>>(), output);
+    }
+
+    #[test]
+    fn tlv_writer_record_nested() {
+        let cursor = Cursor::new(Vec::<u8>::new());
+        let mut tlv = TlvWriter::new(cursor);
+
+        let mut record = tlv.begin_record().unwrap();
+        record.write_item(b"Foo").unwrap();
+        let mut nested = record.begin_record().unwrap();
+        nested.write_all(b"Bar").unwrap();
+        nested.finish().unwrap();
+        record.finish().unwrap();
+        tlv.write_record(b"Baz").unwrap();
+
+        let mut cursor = tlv.into_inner();
+        cursor.set_position(0);
+
+        let mut tlv_reader = TlvReader::new(cursor);
+        let list = tlv_reader.read_list::<Vec<u8>>().unwrap();
+        assert_eq!(vec![b"Foo".to_vec(), b"Bar".to_vec()], list);
+        assert_eq!(b"Baz", tlv_reader.next().unwrap().unwrap().as_slice());
+    }
+
+    #[test]
+    fn tlv_writer_record_drop() {
+        let mut tlv = TlvWriter::new(Cursor::new(Vec::<u8>::new()));
+
+        {
+            let mut record = tlv.begin_record().unwrap();
+            record.write_item(b"Foo").unwrap();
+            record.begin_record().unwrap().write_all(b"Bar").unwrap();
+        }
+        tlv.write_record(b"Baz").unwrap();
+
+        let mut cursor = tlv.into_inner();
+        cursor.set_position(0);
+
+        let mut tlv_reader = TlvReader::new(cursor);
+        let list = tlv_reader.read_list::<Vec<u8>>().unwrap();
+        assert_eq!(vec![b"Foo".to_vec(), b"Bar".to_vec()], list);
+        assert_eq!(b"Baz", tlv_reader.next().unwrap().unwrap().as_slice());
+
+        // The header can't be patched inside an open container
+        let mut tlv = TlvWriter::new(Cursor::new(Vec::<u8>::new()));
+        tlv.begin_container().unwrap();
+        assert!(tlv.begin_record().is_err());
+    }
+
+    #[test]
+    fn tlv_writer_short_writes() {
+        let buf: Vec<u8> = iter::repeat(())
+            .take(300)
+            .enumerate()
+            .map(|(i, _)| i as u8)
+            .collect();
+
+        let mut tlv = TlvWriter::new(ShortWriter(vec![]));
+        assert_eq!(303, tlv.write_record(buf.as_slice()).unwrap());
+        assert_eq!(1, tlv.write_record(&[]).unwrap());
+
+        let result = tlv.into_inner().0;
+
+        assert_eq!(&[0xf2u8, 0x2c, 0x01], &result[..3]);
+        assert_eq!(buf.as_slice(), &result[3..303]);
+        assert_eq!(&[0xf0u8], &result[303..]);
+    }
+
+    #[derive(Clone)]
+    struct Even(u8);
+
+    impl TryFrom<Even> for Vec<u8> {
+        type Error = Error;
+
+        fn try_from(n: Even) -> Result<Self, Error> {
+            if n.0 & 1 == 1 {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "The number is odd",
+                )));
+            }
+
+            Ok(vec![n.0])
+        }
+    }
+
+    #[test]
+    fn tlv_writer_try_list() {
+        let mut tlv = TlvWriter::new(vec![]);
+        tlv.try_write_list(&[Even(0), Even(2)]).unwrap();
+        assert!(tlv.try_write_list(&[Even(4), Even(6), Even(7)]).is_err());
+        tlv.write_record(b"Foo").unwrap();
+
+        let v = tlv.into_inner();
+        let mut tlv_reader = TlvReader::new(v.as_slice());
+        assert_eq!(
+            vec![vec![0u8], vec![2u8]],
+            tlv_reader.read_list::<Vec<u8>>().unwrap()
+        );
+        assert_eq!(b"Foo", tlv_reader.next().unwrap().unwrap().as_slice());
+        assert!(tlv_reader.next().is_none());
+    }
+
+    /// Sequence whose second element can't be serialized
+    struct Unsupported;
+
+    impl Serialize for Unsupported {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            use serde::ser::SerializeSeq;
+
+            let mut seq = serializer.serialize_seq(Some(2))?;
+            seq.serialize_element(&1u8)?;
+            seq.serialize_element(&2u128)?;
+            seq.end()
+        }
+    }
+
+    #[test]
+    fn tlv_writer_failed_serialize() {
+        let mut tlv = TlvWriter::new(vec![]);
+        tlv.set_compact_structs(true);
+        assert!((1u8, vec![(2u8, Unsupported)]).serialize(&mut tlv).is_err());
+        assert!(Unsupported.serialize(&mut tlv).is_err());
+
+        tlv.write_record(b"Foo").unwrap();
+        5u8.serialize(&mut tlv).unwrap();
+
+        let mut expected = vec![0xf1, 0x03];
+        expected.extend_from_slice(b"Foo");
+        expected.extend(to_vec(&5u8).unwrap());
+        assert_eq!(expected, tlv.into_inner());
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Id(u64);
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Kind {
+        Transfer { amount: u64, fee: u32 },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Block {
+        version: u8,
+        height: u64,
+        last: bool,
+        hash: [u8; 4],
+        name: String,
+        parent: Option<u64>,
+        id: Id,
+        kind: Kind,
+    }
+
+    #[test]
+    fn tlv_writer_compact_structs() {
+        let block = Block {
+            version: 2,
+            height: 1 << 40,
+            last: true,
+            hash: [0xab; 4],
+            name: String::from("Foo"),
+            parent: Some(5),
+            id: Id(7),
+            kind: Kind::Transfer { amount: 9, fee: 1 },
+        };
+
+        let mut writer = TlvWriter::new(vec![]);
+        writer.set_compact_structs(true);
+        block.serialize(&mut writer).unwrap();
+        let size = writer.serialized_size(&block).unwrap();
+        let v = writer.into_inner();
+
+        // Fixed-size fields have no header, while the others keep theirs
+        let payload = 1 + 8 + 1 + (2 + 4) + (2 + 3) + (2 + 2 + 8) + 8 + (2 + 6 + 8 + 4);
+        assert_eq!(2 + payload, v.len());
+        assert_eq!(size, v.len());
+        assert_eq!(&[0xf1, payload as u8, 0x02, 0x00], &v[..4]);
+        assert!(v.len() < to_vec(&block).unwrap().len());
+
+        let mut reader = TlvReader::new(v.as_slice());
+        reader.set_compact_structs(true);
+        assert_eq!(block, Block::deserialize(&mut reader).unwrap());
+        assert!(reader.next().is_none());
+
+        let mut reader = TlvReader::new(v.as_slice());
+        assert!(Block::deserialize(&mut reader).is_err());
+    }
+
+    #[test]
+    fn tlv_writer_packed_seq() {
+        let bytes: Vec<u8> = iter::repeat(())
+            .take(300)
+            .enumerate()
+            .map(|(i, _)| i as u8)
+            .collect();
+
+        let v = to_vec(&bytes).unwrap();
+        assert_eq!(&[0xe2u8, 0x2c, 0x01], &v[..3]);
+        assert_eq!(bytes.as_slice(), &v[3..]);
+        assert_eq!(bytes, from_slice::<Vec<u8>>(v.as_slice()).unwrap());
+
+        let numbers = vec![1u64, 2, u64::MAX];
+        let v = to_vec(&numbers).unwrap();
+        assert_eq!(&[0xe1u8, 0x18, 0x01, 0x00], &v[..4]);
+        assert_eq!(2 + 24, v.len());
+        assert_eq!(v.len(), serialized_size(&numbers).unwrap());
+        assert_eq!(v, to_value(&numbers).unwrap().to_vec().unwrap());
+        assert_eq!(numbers, from_slice::<Vec<u64>>(v.as_slice()).unwrap());
+        assert_eq!(
+            numbers,
+            from_value::<Vec<u64>>(to_value(&numbers).unwrap()).unwrap()
+        );
+
+        let mut writer = TlvWriter::new(vec![]);
+        writer.encode(&numbers).unwrap();
+        assert_eq!(v, writer.into_inner());
+        let mut reader = TlvReader::new(v.as_slice());
+        assert_eq!(numbers, reader.decode::<Vec<u64>>().unwrap());
+
+        // Empty and non-numeric sequences are regular containers
+        assert_eq!(vec![0xf0u8], to_vec(&Vec::<u32>::new()).unwrap());
+        let options = vec![Some(1u8), None];
+        let v = to_vec(&options).unwrap();
+        assert_eq!(0xf1u8, v[0]);
+        assert_eq!(
+            options,
+            from_slice::<Vec<Option<u8>>>(v.as_slice()).unwrap()
+        );
+
+        // A packed payload that is not a multiple of the width is rejected
+        assert!(from_slice::<Vec<u32>>(&[0xe1, 0x03, 0x01, 0x02, 0x03]).is_err());
+
+        // Numeric elements of different types fall back to one record per element
+        #[derive(Serialize)]
+        #[serde(untagged)]
+        enum Number {
+            Small(u8),
+            Large(u64),
+        }
+
+        let numbers = vec![Number::Small(1), Number::Small(2), Number::Large(3)];
+        let v = to_vec(&numbers).unwrap();
+        assert_eq!(to_vec(&(1u8, 2u8, 3u64)).unwrap(), v);
+        assert_eq!(v.len(), serialized_size(&numbers).unwrap());
+        assert_eq!(v, to_value(&numbers).unwrap().to_vec().unwrap());
+
+        let mut writer = TlvWriter::new(vec![]);
+        writer.set_layouts(
+            crate::size::layouts_with(&numbers, IntEncoding::default(), false).unwrap(),
+        );
+        numbers.serialize(&mut writer).unwrap();
+        assert_eq!(v, writer.into_inner());
+
+        // 128-bit integers are neither packed nor written as records
+        assert!(to_vec(&1u128).is_err());
+        assert!(to_vec(&vec![1u128, 2]).is_err());
+    }
+
+    #[test]
+    fn tlv_writer_layouts() {
+        let value = (
+            vec![Some(String::from("Foo")), None],
+            Kind::Transfer { amount: 9, fee: 1 },
+            vec![7u16; 300],
+        );
+        let v = to_vec(&value).unwrap();
+
+        // With the layouts computed in advance, nothing is buffered
+        let mut writer = TlvWriter::new(vec![]);
+        writer
+            .set_layouts(crate::size::layouts_with(&value, IntEncoding::default(), false).unwrap());
+        value.serialize(&mut writer).unwrap();
+        assert_eq!(0, writer.buf.capacity());
+        assert_eq!(v, writer.into_inner());
+
+        // Maps of a canonical writer are buffered to sort their entries
+        let map: BTreeMap<u8, Vec<u8>> = (0..4).map(|i| (i, vec![i; 2])).collect();
+        let value = (1u8, vec![map.clone(), map]);
+        let mut writer = TlvWriter::new(vec![]);
+        writer.set_canonical(true);
+        writer
+            .set_layouts(crate::size::layouts_with(&value, IntEncoding::default(), false).unwrap());
+        value.serialize(&mut writer).unwrap();
+        assert_eq!(to_vec(&value).unwrap(), writer.into_inner());
+
+        // A value serialized differently than its layouts is rejected
+        struct Growing(std::cell::Cell<u8>);
+
+        impl Serialize for Growing {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let n = self.0.get();
+                self.0.set(n + 1);
+                serializer.collect_seq(0..n)
+            }
+        }
+
+        let value = (1u8, Growing(std::cell::Cell::new(1)));
+        let mut writer = TlvWriter::new(vec![]);
+        writer
+            .set_layouts(crate::size::layouts_with(&value, IntEncoding::default(), false).unwrap());
+        assert!(value.serialize(&mut writer).is_err());
+    }
+
+    #[test]
+    fn tlv_writer_list_in_container() {
+        let mut writer = TlvWriter::new(vec![]);
+        writer.begin_container().unwrap();
+        writer.write_record(&[0x01]).unwrap();
+        writer.begin_list().write_item(&[0x02]).unwrap();
+        writer.write_record(&[0x03]).unwrap();
+        writer.end_container().unwrap();
+        let v = writer.into_inner();
+
+        assert_eq!(
+            vec![0xf1, 0x0b, 0xf1, 0x01, 0x01, 0xf1, 0x03, 0xf1, 0x01, 0x02, 0xf1, 0x01, 0x03],
+            v
+        );
+
+        // A list written by the encoding of a struct field stays inside the struct
+        struct Tagged(Vec<Vec<u8>>);
+
+        impl TlvEncode for Tagged {
+            fn encode<W: io::Write>(&self, writer: &mut TlvWriter<W>) -> Result<(), Error> {
+                writer.write_container(|w| {
+                    w.write_record(b"Tags")?;
+                    w.write_list(self.0.iter()).map(|_| ())
+                })
+            }
+        }
+
+        let mut writer = TlvWriter::new(vec![]);
+        writer
+            .encode(&Tagged(vec![b"Foo".to_vec(), b"Bar".to_vec()]))
+            .unwrap();
+        writer.write_record(b"Baz").unwrap();
+        let v = writer.into_inner();
+
+        let mut reader = TlvReader::new(v.as_slice());
+        reader
+            .read_container(|r| {
+                assert_eq!(b"Tags", r.next_payload()?.as_slice());
+                assert_eq!(
+                    vec![b"Foo".to_vec(), b"Bar".to_vec()],
+                    r.read_list::<Vec<u8>>()?
+                );
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(b"Baz", reader.next_payload().unwrap().as_slice());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn tlv_writer_options() {
+        // Values with an empty payload are distinguished from None
+        let text = Some(String::new());
+        let v = to_vec(&text).unwrap();
+        assert_eq!(vec![0xf1, 0x01, 0xf0], v);
+        assert_eq!(text, from_slice::<Option<String>>(v.as_slice()).unwrap());
+        assert_eq!(
+            v.len(),
+            TlvWriter::new(vec![]).serialized_size(&text).unwrap()
+        );
+        assert_eq!(v, to_value(&text).unwrap().to_vec().unwrap());
+        assert_eq!(
+            text,
+            from_value(TlvValue::parse(v.as_slice(), 8).unwrap()).unwrap()
+        );
+
+        let mut writer = TlvWriter::new(vec![]);
+        writer.encode(&text).unwrap();
+        assert_eq!(v, writer.into_inner());
+        let mut reader = TlvReader::new(v.as_slice());
+        assert_eq!(text, reader.decode::<Option<String>>().unwrap());
+
+        let bytes = Some(Vec::<u8>::new());
+        let v = to_vec(&bytes).unwrap();
+        assert_eq!(bytes, from_slice::<Option<Vec<u8>>>(v.as_slice()).unwrap());
+        assert_eq!(
+            bytes,
+            from_value(TlvValue::parse(v.as_slice(), 8).unwrap()).unwrap()
+        );
+
+        let unit = Some(());
+        let v = to_vec(&unit).unwrap();
+        assert_eq!(unit, from_slice::<Option<()>>(v.as_slice()).unwrap());
+        assert_eq!(unit, from_value(to_value(&unit).unwrap()).unwrap());
+
+        let none: Option<String> = None;
+        let v = to_vec(&none).unwrap();
+        assert_eq!(vec![0xf0], v);
+        assert_eq!(none, from_slice::<Option<String>>(v.as_slice()).unwrap());
+        assert_eq!(
+            none,
+            from_value(TlvValue::parse(v.as_slice(), 8).unwrap()).unwrap()
+        );
+
+        // An option of more than a single record is rejected
+        let v = [0xf1, 0x06, 0xf1, 0x01, 0x01, 0xf1, 0x01, 0x02];
+        assert!(from_slice::<Option<u8>>(&v).is_err());
+        let value = TlvValue::parse(&v[..], 8).unwrap();
+        assert!(from_value::<Option<u8>>(value).is_err());
+    }
 }